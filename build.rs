@@ -5,8 +5,6 @@ mod mkv;
 
 use mkv::MarkovData;
 
-#[warn(clippy::pedantic)]
-
 fn main() {
     println!("cargo:rerun-if-changed=markov/");
     println!("cargo:rerun-if-changed=csv/");
@@ -18,8 +16,12 @@ fn main() {
                 println!($src_path);
                 let markov_data: MarkovData = MarkovData::from_csv($src_path).unwrap();
                 println!($dest_path);
-                let mut f = fs::File::create($dest_path).unwrap();
-                f.write_all(&markov_data.to_bytes()).unwrap();
+                let bytes = markov_data.to_bytes();
+                // rewriting an unchanged file would make cargo rerun this script on every build
+                if fs::read($dest_path).ok().as_deref() != Some(&bytes[..]) {
+                    let mut f = fs::File::create($dest_path).unwrap();
+                    f.write_all(&bytes).unwrap();
+                }
             )*
         }
     }
@@ -134,7 +134,8 @@ def main(argv):
         for k, v in jsondict["trade_connections"].items():
             k_l = ast.literal_eval(k)
             k_a = ((k_l[0], k_l[1]), (k_l[2], k_l[3]))
-            sim.trade_connections[k_a] = v
+            # newer saves store the whole route, with the trade count under "trades"
+            sim.trade_connections[k_a] = v["trades"] if isinstance(v, dict) else v
         sim.init_magic()
     elif jsondict["file_type"] == "gen":
         sim.AllItems = ["Fish"]
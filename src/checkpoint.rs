@@ -0,0 +1,202 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::World;
+
+const PREFIX: &str = "checkpoint_";
+
+/// Periodically writes the full world state to a directory, keeping only the newest few.
+///
+/// Checkpoints are named after the world's seed as well as the year, so several runs can share
+/// a directory without pruning each other's checkpoints.
+pub struct Checkpoints {
+    dir: PathBuf,
+    every: u32,
+    keep: usize,
+}
+
+impl Checkpoints {
+    pub fn new(dir: impl Into<PathBuf>, every: u32, keep: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            every: every.max(1),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Write a checkpoint if the world is on a checkpoint year
+    ///
+    /// # Errors
+    /// Fails if the checkpoint directory can't be created or written to
+    pub fn tick(&self, world: &World) -> io::Result<()> {
+        if world.current_year().is_multiple_of(self.every) {
+            self.write(world)?;
+        }
+        Ok(())
+    }
+
+    /// Write a checkpoint for the world's current year and delete the run's oldest ones, along
+    /// with any left over from a longer run with the same seed
+    ///
+    /// # Errors
+    /// Fails if the checkpoint directory can't be created or written to
+    pub fn write(&self, world: &World) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let (run, year) = (world.seed(), world.current_year());
+        write_atomic(
+            &self.dir.join(format!("{PREFIX}{run}_{year:010}.json")),
+            world.save().as_bytes(),
+        )?;
+        let mut checkpoints = list(&self.dir)?;
+        checkpoints.retain(|checkpoint| checkpoint.run == Some(run));
+        let stale = checkpoints
+            .iter()
+            .filter(|checkpoint| checkpoint.year > year);
+        let oldest = checkpoints
+            .iter()
+            .filter(|checkpoint| checkpoint.year <= year)
+            .rev()
+            .skip(self.keep);
+        for checkpoint in stale.chain(oldest) {
+            fs::remove_file(&checkpoint.path)?;
+        }
+        Ok(())
+    }
+
+    /// The most recently written checkpoint in `dir`, if there is one. Only the run started
+    /// with `run` is looked at, if it's given.
+    pub fn latest(dir: impl AsRef<Path>, run: Option<u64>) -> Option<PathBuf> {
+        list(dir.as_ref())
+            .ok()?
+            .into_iter()
+            .filter(|checkpoint| run.is_none() || checkpoint.run == run)
+            .max_by_key(|checkpoint| (checkpoint.written, checkpoint.year))
+            .map(|checkpoint| checkpoint.path)
+    }
+}
+
+/// A checkpoint file, as found in a checkpoint directory
+struct Checkpoint {
+    /// The seed of the run that wrote it; `None` for checkpoints from before they were named
+    /// after it
+    run: Option<u64>,
+    year: u32,
+    written: SystemTime,
+    path: PathBuf,
+}
+
+/// All the checkpoints in `dir`, oldest year first
+fn list(dir: &Path) -> io::Result<Vec<Checkpoint>> {
+    let mut checkpoints: Vec<Checkpoint> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix(PREFIX)?
+                .strip_suffix(".json")?;
+            let (run, year) = match name.split_once('_') {
+                Some((run, year)) => (Some(run.parse().ok()?), year),
+                None => (None, name),
+            };
+            Some(Checkpoint {
+                run,
+                year: year.parse().ok()?,
+                written: entry.metadata().and_then(|meta| meta.modified()).ok()?,
+                path,
+            })
+        })
+        .collect();
+    checkpoints.sort_unstable_by_key(|checkpoint| checkpoint.year);
+    Ok(checkpoints)
+}
+
+/// Write to a temporary file and rename it into place, so `path` is never half-written
+///
+/// # Errors
+/// Fails if the temporary file can't be written or renamed
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    // the rename itself only survives a crash once the directory is synced too
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{list, Checkpoints};
+    use crate::{mkv::MarkovCollection, World};
+
+    #[test]
+    fn keeps_newest_checkpoints() {
+        let dir = std::env::temp_dir().join("continent_keeps_newest_checkpoints");
+        let _ = fs::remove_dir_all(&dir);
+        let markov = MarkovCollection::builtin();
+        let mut world = World::load("json_data/tiny.json", Some(7), &markov).unwrap();
+        let checkpoints = Checkpoints::new(&dir, 2, 2);
+        for _ in 0..7 {
+            world.step(1, &markov);
+            checkpoints.tick(&world).unwrap();
+        }
+        let years: Vec<u32> = list(&dir).unwrap().iter().map(|c| c.year).collect();
+        assert_eq!(years, vec![4, 6]);
+        let latest = Checkpoints::latest(&dir, None).unwrap();
+        assert_eq!(
+            World::load(latest, None, &markov).unwrap().current_year(),
+            6
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fresh_run_replaces_a_longer_one() {
+        let dir = std::env::temp_dir().join("continent_fresh_run_replaces_a_longer_one");
+        let _ = fs::remove_dir_all(&dir);
+        let markov = MarkovCollection::builtin();
+        let checkpoints = Checkpoints::new(&dir, 1, 2);
+        let mut long = World::load("json_data/tiny.json", Some(7), &markov).unwrap();
+        long.step(5, &markov);
+        checkpoints.write(&long).unwrap();
+        // a run in the same directory keeps its own checkpoints
+        let mut other = World::load("json_data/tiny.json", Some(8), &markov).unwrap();
+        other.step(1, &markov);
+        checkpoints.write(&other).unwrap();
+        // and starting the first one over drops its checkpoints from the longer run
+        let mut again = World::load("json_data/tiny.json", Some(7), &markov).unwrap();
+        again.step(2, &markov);
+        checkpoints.write(&again).unwrap();
+        let mut kept: Vec<(Option<u64>, u32)> = list(&dir)
+            .unwrap()
+            .iter()
+            .map(|c| (c.run, c.year))
+            .collect();
+        kept.sort_unstable();
+        // named after the seeds the runs were started with
+        assert_eq!(kept, vec![(Some(7), 2), (Some(8), 1)]);
+        let latest = Checkpoints::latest(&dir, None).unwrap();
+        assert_eq!(
+            World::load(latest, None, &markov).unwrap().current_year(),
+            2
+        );
+        // or a run can be picked out by its seed
+        let other = Checkpoints::latest(&dir, Some(8)).unwrap();
+        assert_eq!(World::load(other, None, &markov).unwrap().seed(), 8);
+        assert!(Checkpoints::latest(&dir, Some(9)).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,10 @@
-use crate::sim::usize_to_vec;
 #[allow(clippy::wildcard_imports)]
 use crate::{
     magic::{Ability, AbilityType, MagicSystem, MaterialType},
+    sim::{RouteEvent, RouteKind, Trade},
     *,
 };
-use std::fs;
-
-pub fn json_array_to_usize(arr: &JsonValue, config: &Config) -> Option<usize> {
-    let JsonValue::Array(coords) = arr else { return None };
-    let x = json_int(coords.get(0)?)? as usize;
-    let y = json_int(coords.get(1)?)? as usize;
-    Some(x + y * config.world_size.0)
-}
+use std::{fs, hash::BuildHasher};
 
 pub fn json_string(jsonvalue: &JsonValue) -> Option<String> {
     match jsonvalue {
@@ -26,9 +19,10 @@ pub fn json_int(jsonvalue: &JsonValue) -> Option<i32> {
     Some(num.as_fixed_point_i64(0).unwrap_or_default() as i32)
 }
 
-pub fn json_float(jsonvalue: &JsonValue, depth: u16) -> Option<f32> {
+/// Read a float without rounding it, so simulation state survives a save and load unchanged
+pub fn json_float(jsonvalue: &JsonValue) -> Option<f32> {
     let JsonValue::Number(num) = jsonvalue else { return None };
-    Some(num.as_fixed_point_i64(depth).unwrap_or_default() as f32 / 10.0f32.powf(depth as f32))
+    Some(f64::from(*num) as f32)
 }
 
 pub trait Jsonizable: Sized {
@@ -85,11 +79,12 @@ impl<T: Jsonizable> Jsonizable for Vec<T> {
 //     }
 // }
 
-impl<T: Jsonizable> Jsonizable for HashMap<String, T> {
+impl<T: Jsonizable, S: BuildHasher + Default> Jsonizable for HashMap<String, T, S> {
     fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
         let mut object = json::object::Object::new();
-        self.iter()
-            .for_each(|(key, value)| object.insert(key, value.jsonize(config, items)));
+        for (key, value) in self {
+            object.insert(key, value.jsonize(config, items));
+        }
         JsonValue::Object(object)
     }
 
@@ -97,7 +92,7 @@ impl<T: Jsonizable> Jsonizable for HashMap<String, T> {
         let JsonValue::Object(object) = src else {
             return None
         };
-        let mut res = Self::new();
+        let mut res = Self::default();
         for (key, value) in object.iter() {
             res.insert(String::from(key), T::dejsonize(value, config, items)?);
         }
@@ -107,19 +102,33 @@ impl<T: Jsonizable> Jsonizable for HashMap<String, T> {
 
 impl SuperJsonizable for Items {
     fn s_jsonize(&self) -> JsonValue {
+        // json objects keep insertion order, so the item indices survive a save and load
+        macro_rules! item_type {
+            ($list: expr => |$a: ident| $values: expr) => {{
+                let mut object = json::object::Object::new();
+                for $a in &$list {
+                    object.insert(&$a.name, JsonValue::from($values));
+                }
+                JsonValue::Object(object)
+            }};
+        }
+        // price overrides go in an object after the numbers
+        let with_overrides = |numbers: Vec<u8>, overrides: &PriceOverrides| {
+            let mut values: Vec<JsonValue> = numbers.into_iter().map(JsonValue::from).collect();
+            if overrides.iter().next().is_some() {
+                let mut object = json::object::Object::new();
+                for (key, value) in overrides.iter() {
+                    object.insert(key, JsonValue::from(value));
+                }
+                values.push(JsonValue::Object(object));
+            }
+            values
+        };
         object! {
-            Animals: self.animals.iter()
-                .map(|a| (a.name.clone(), vec![a.rarity, a.abundance, a.value, a.taming]))
-                .collect::<HashMap<String, Vec<u8>>>(),
-            Plants: self.plants.iter()
-                .map(|a| (a.name.clone(), vec![a.rarity, a.abundance, a.value]))
-                .collect::<HashMap<String, Vec<u8>>>(),
-            Gems: self.gems.iter()
-                .map(|a| (a.name.clone(), vec![a.rarity, a.abundance, a.value]))
-                .collect::<HashMap<String, Vec<u8>>>(),
-            Metals: self.metals.iter()
-                .map(|a| (a.name.clone(), vec![a.rarity, a.abundance, a.value]))
-                .collect::<HashMap<String, Vec<u8>>>(),
+            Animals: item_type!(self.animals => |a| with_overrides(vec![a.rarity, a.abundance, a.value, a.taming], &a.overrides)),
+            Plants: item_type!(self.plants => |a| with_overrides(vec![a.rarity, a.abundance, a.value], &a.overrides)),
+            Gems: item_type!(self.gems => |a| with_overrides(vec![a.rarity, a.abundance, a.value], &a.overrides)),
+            Metals: item_type!(self.metals => |a| with_overrides(vec![a.rarity, a.abundance, a.value], &a.overrides)),
         }
     }
 
@@ -128,8 +137,10 @@ impl SuperJsonizable for Items {
         macro_rules! item_type {
             ($key: expr) => {{
                 let Some(JsonValue::Object(obj)) = object.get($key) else { return None };
+                // one bad item fails the whole list, since skipping it would shift the indices
+                // of every item after it
                 obj.iter()
-                    .filter_map(|(name, values)| match values {
+                    .map(|(name, values)| match values {
                         JsonValue::Array(arr) => Some(ItemType {
                             name: String::from(name),
                             rarity: json_int(arr.get(0)?)? as u8,
@@ -139,10 +150,19 @@ impl SuperJsonizable for Items {
                                 .get(3)
                                 .map_or(0, |jsonvalue| json_int(jsonvalue).unwrap_or(0))
                                 as u8,
+                            overrides: {
+                                let mut overrides = PriceOverrides::default();
+                                if let Some(JsonValue::Object(prices)) = arr.last() {
+                                    for (key, value) in prices.iter() {
+                                        overrides.set(key, json_float(value)?)?;
+                                    }
+                                }
+                                overrides
+                            },
                         }),
                         _ => None,
                     })
-                    .collect()
+                    .collect::<Option<Vec<_>>>()?
             }};
         }
 
@@ -173,6 +193,11 @@ impl Items {
                 .map(str::parse::<u8>)
                 .filter_map(Result::ok)
                 .collect::<Vec<u8>>();
+            // anything like `price=30` after the numbers overrides part of the pricing
+            let mut overrides = PriceOverrides::default();
+            for (key, value) in chunk[1].split(',').filter_map(|part| part.split_once('=')) {
+                overrides.set(key, value.trim().parse().ok()?)?;
+            }
             let &rarity = numerical_values.first()?;
             let &abundance = numerical_values.get(1)?;
             let &value = numerical_values.get(2)?;
@@ -190,6 +215,7 @@ impl Items {
                 abundance,
                 value,
                 taming,
+                overrides,
             });
         }
         Some(Self::from_item_types(plants, metals, gems, animals))
@@ -201,8 +227,8 @@ impl Jsonizable for Npc {
         object! {
             name: self.name.clone(),
             title: self.title.clone(),
-            pos: usize_to_vec(self.pos, config),
-            origin: usize_to_vec(self.origin, config),
+            pos: config.grid().coord(self.pos),
+            origin: config.grid().coord(self.origin),
             birth: self.birth,
             age: self.age,
             race: "Human",
@@ -222,8 +248,8 @@ impl Jsonizable for Npc {
         Some(Self {
             name: json_string(object.get("name")?)?,
             title: json_string(object.get("title")?)?,
-            pos: json_array_to_usize(object.get("pos")?, config)?,
-            origin: json_array_to_usize(object.get("origin")?, config)?,
+            pos: config.grid().dejsonize_tile(object.get("pos")?)?,
+            origin: config.grid().dejsonize_tile(object.get("origin")?)?,
             birth: json_int(object.get("birth")?)? as u32,
             age: json_int(object.get("age")?)? as u32,
             alive: *alive,
@@ -248,14 +274,32 @@ impl SuperJsonizable for Config {
         object! {
             GEN_RADIUS: self.gen_radius,
             WORLD_SIZE: vec![self.world_size.0, self.world_size.1],
+            TOPOLOGY: self.topology.as_ref(),
+            TILE_SHAPE: self.tile_shape.as_ref(),
+            LAND_RATIO: self.land_ratio,
+            CONTINENTS: self.continents,
+            ARCHIPELAGO_DENSITY: self.archipelago_density,
+            COASTLINE_ROUGHNESS: self.coastline_roughness,
             COASTAL_CITY_DENSITY: self.coastal_city_density,
             INLAND_CITY_DENSITY: self.inland_city_density,
+            CITY_COUNT: self.city_count,
+            CITY_SPACING: self.city_spacing,
             PRODUCTION_CONSTANT: self.production_constant,
             POPULATION_CONSTANT: self.population_constant,
             NOTABLE_NPC_THRESHOLD: self.notable_npc_threshold,
             MINERAL_DEPLETION: self.mineral_depletion,
+            CONSUMPTION: self.consumption,
+            CREDIT: self.credit,
+            MINTING: self.minting,
             TRADE_VOLUME: self.trade_volume,
             TRADE_QUANTITY: self.trade_quantity,
+            TRADE_REACH: self.trade_reach,
+            ROUTE_GROWTH: self.route_growth,
+            ROUTE_DECAY: self.route_decay,
+            ROUTE_DANGER: self.route_danger,
+            PROSPERITY: self.prosperity,
+            SNAPSHOT_INTERVAL: self.snapshot_interval,
+            LEDGER_YEARS: self.ledger_years,
             ARMY_SIZE: 200,
             ARMY_PARAMETER: 0.7
         }
@@ -265,22 +309,104 @@ impl SuperJsonizable for Config {
         // println!("dj config");
         let JsonValue::Object(object) = src else { return None };
         let Some(JsonValue::Array(world_size)) = object.get("WORLD_SIZE") else { return None };
-        Some(Self {
+        let config = Self {
             gen_radius: json_int(object.get("GEN_RADIUS")?)? as usize,
             world_size: {
-                let x = json_int(world_size.get(0)?)? as usize;
+                let x = json_int(world_size.first()?)? as usize;
                 let y = json_int(world_size.get(1)?)? as usize;
                 (x, y)
             },
-            coastal_city_density: json_float(object.get("COASTAL_CITY_DENSITY")?, 4)?,
-            inland_city_density: json_float(object.get("INLAND_CITY_DENSITY")?, 4)?,
-            production_constant: json_float(object.get("PRODUCTION_CONSTANT")?, 6)?,
-            population_constant: json_float(object.get("POPULATION_CONSTANT")?, 6)?,
-            mineral_depletion: json_float(object.get("MINERAL_DEPLETION")?, 6)?,
+            coastal_city_density: json_float(object.get("COASTAL_CITY_DENSITY")?)?,
+            inland_city_density: json_float(object.get("INLAND_CITY_DENSITY")?)?,
+            production_constant: json_float(object.get("PRODUCTION_CONSTANT")?)?,
+            population_constant: json_float(object.get("POPULATION_CONSTANT")?)?,
+            mineral_depletion: json_float(object.get("MINERAL_DEPLETION")?)?,
             notable_npc_threshold: json_int(object.get("NOTABLE_NPC_THRESHOLD")?)? as u8,
-            trade_volume: json_float(object.get("TRADE_VOLUME")?, 3)?,
-            trade_quantity: json_int(object.get("TRADE_QUANTITY")?)?,
-        })
+            trade_volume: json_float(object.get("TRADE_VOLUME")?)?,
+            // older gen and save files lack everything below, so each falls back to how
+            // maps were made and cities were run before the key was added
+            topology: match object.get("TOPOLOGY").and_then(json_string) {
+                None => Topology::Flat,
+                Some(topology) => Topology::iter().find(|t| t.as_ref() == topology)?,
+            },
+            tile_shape: match object.get("TILE_SHAPE").and_then(json_string) {
+                None => TileShape::Square,
+                Some(shape) => TileShape::iter().find(|t| t.as_ref() == shape)?,
+            },
+            land_ratio: object
+                .get("LAND_RATIO")
+                .and_then(json_float)
+                .map_or(0.4, |ratio| ratio.clamp(0.0, 1.0)),
+            continents: object
+                .get("CONTINENTS")
+                .and_then(json_int)
+                .map_or(1, |continents| continents.max(0) as usize),
+            archipelago_density: object
+                .get("ARCHIPELAGO_DENSITY")
+                .and_then(json_float)
+                .map_or(0.0, |density| density.max(0.0)),
+            coastline_roughness: object
+                .get("COASTLINE_ROUGHNESS")
+                .and_then(json_float)
+                .map_or(0.5, |roughness| roughness.clamp(0.0, 1.0)),
+            // without a count, the densities decide how many cities there are
+            city_count: object
+                .get("CITY_COUNT")
+                .and_then(json_int)
+                .map(|count| count.max(0) as usize),
+            city_spacing: object
+                .get("CITY_SPACING")
+                .and_then(json_float)
+                .map_or(2.0, |spacing| spacing.max(1.0)),
+            consumption: object
+                .get("CONSUMPTION")
+                .and_then(json_float)
+                .map_or(0.05, |consumption| consumption.max(0.0)),
+            // a city can pay for imports by going into debt, and strikes part of its coin
+            // metal into new coins each year
+            credit: object
+                .get("CREDIT")
+                .and_then(json_float)
+                .map_or(1.0, |credit| credit.max(0.0)),
+            minting: object
+                .get("MINTING")
+                .and_then(json_float)
+                .map_or(0.1, |minting| minting.clamp(0.0, 1.0)),
+            trade_quantity: object
+                .get("TRADE_QUANTITY")
+                .and_then(json_int)
+                .unwrap_or(20),
+            trade_reach: object
+                .get("TRADE_REACH")
+                .and_then(json_float)
+                .map_or(5.0, |reach| reach.max(0.0)),
+            route_growth: object
+                .get("ROUTE_GROWTH")
+                .and_then(json_float)
+                .map_or(0.1, |growth| growth.max(0.0)),
+            route_decay: object
+                .get("ROUTE_DECAY")
+                .and_then(json_float)
+                .map_or(0.02, |decay| decay.max(0.0)),
+            route_danger: object
+                .get("ROUTE_DANGER")
+                .and_then(json_float)
+                .map_or(0.05, |danger| danger.max(0.0)),
+            prosperity: object
+                .get("PROSPERITY")
+                .and_then(json_int)
+                .map_or(500, |prosperity| prosperity.max(1)),
+            snapshot_interval: object
+                .get("SNAPSHOT_INTERVAL")
+                .and_then(json_int)
+                .map_or(100, |interval| interval.max(1) as u32),
+            ledger_years: object
+                .get("LEDGER_YEARS")
+                .and_then(json_int)
+                .map_or(100, |years| years.max(1) as u32),
+        };
+        // a torus of hexes with an odd height would join mismatched rows across the seam
+        config.grid().seams_line_up().then_some(config)
     }
 }
 
@@ -327,49 +453,105 @@ impl Jsonizable for MagicSystem {
         let Some(JsonValue::Array(arr)) = object.get("Material") else { return None };
         let Some(JsonValue::Array(numbers)) = arr.get(1) else { return None };
         let material = ItemType {
-            name: json_string(arr.get(0)?)?,
-            rarity: json_int(numbers.get(0)?)? as u8,
+            name: json_string(arr.first()?)?,
+            rarity: json_int(numbers.first()?)? as u8,
             abundance: json_int(numbers.get(1)?)? as u8,
             value: json_int(numbers.get(2)?)? as u8,
             taming: 0,
+            overrides: PriceOverrides::default(),
+        };
+        let material_type = match json_string(arr.get(2)?)?.as_ref() {
+            "Gem" => MaterialType::Gem,
+            "Metal" => MaterialType::Metal,
+            "Plant" => MaterialType::Plant,
+            _ => return None,
         };
+        // the material is added after the gen file's items, so search from the end in case
+        // it shares a name with one of them
+        let index = items.iter().rev().find_map(|(index, item)| {
+            match (item, material_type) {
+                (Item::Plant(plant), MaterialType::Plant) => {
+                    items.plants[plant as usize].name == material.name
+                }
+                (Item::Metal(metal), MaterialType::Metal) => {
+                    items.metals[metal as usize].name == material.name
+                }
+                (Item::Gem(gem), MaterialType::Gem) => {
+                    items.gems[gem as usize].name == material.name
+                }
+                _ => false,
+            }
+            .then_some(index)
+        });
         Some(Self {
-            material: material.clone(),
-            material_type: match json_string(arr.get(2)?)?.as_ref() {
-                "Gem" => MaterialType::Gem,
-                "Metal" => MaterialType::Metal,
-                "Plant" => MaterialType::Plant,
-                _ => return None,
-            },
+            material,
+            material_type,
             name: json_string(object.get("Name")?)?,
             abilities: Vec::<Ability>::dejsonize(object.get("Abilities")?, config, items)?,
-            index: items.all.iter().position(|item| match item {
-                Item::Plant(plant) => items.plants[*plant as usize].name == material.name,
-                Item::Metal(metal) => items.metals[*metal as usize].name == material.name,
-                Item::Gem(gem) => items.gems[*gem as usize].name == material.name,
-                _ => false,
-            }),
+            index,
         })
     }
 }
 
+/// The routes in a save, keyed by the tiles of the cities at either end
+fn dejsonize_trade_connections(
+    tcons: &json::object::Object,
+    config: &Config,
+    items: &Items,
+) -> Option<HashMap<(usize, usize), TradeRoute>> {
+    let mut trade_connections = HashMap::new();
+    for (k, v) in tcons.iter() {
+        let key = {
+            let Ok(JsonValue::Array(arr)) = json::parse(k) else { return None };
+            (
+                config.grid().dejsonize_tile(&JsonValue::Array(vec![
+                    arr.first()?.clone(),
+                    arr.get(1)?.clone(),
+                ]))?,
+                config.grid().dejsonize_tile(&JsonValue::Array(vec![
+                    arr.get(2)?.clone(),
+                    arr.get(3)?.clone(),
+                ]))?,
+            )
+        };
+        let route = match v {
+            // older saves only counted the trades, along a straight line
+            JsonValue::Number(_) => TradeRoute {
+                kind: RouteKind::Land,
+                path: vec![key.0, key.1],
+                cost: config.grid().distance(key.0, key.1),
+                trades: json_int(v)?,
+                strength: TradeRoute::OPENING_STRENGTH,
+                carried: 0.0,
+                value: 0.0,
+            },
+            _ => TradeRoute::dejsonize(v, config, items)?,
+        };
+        trade_connections.insert(key, route);
+    }
+    Some(trade_connections)
+}
+
 impl SuperJsonizable for World {
     fn s_jsonize(&self) -> JsonValue {
         json::object! {
             file_type: "save",
             RegionList: self.region_list.jsonize(&self.config, &self.items),
             CityList: self.city_list.values().cloned().collect::<Vec<City>>().jsonize(&self.config, &self.items),
-            trade_connections: self.trade_connections.iter().map(|((first, second), &strength)| (format!("[{}, {}, {}, {}]", first % self.config.world_size.0, first / self.config.world_size.0, second % self.config.world_size.0, second / self.config.world_size.0), strength)).collect::<HashMap<String, i32>>(),
-            Biomes: {
-                Desert: Terrain::Desert.jsonize(&self.config, &self.items),
-                Forest: Terrain::Forest.jsonize(&self.config, &self.items),
-                Jungle: Terrain::Jungle.jsonize(&self.config, &self.items),
-                Mountain: Terrain::Mountain.jsonize(&self.config, &self.items),
-                Ocean: Terrain::Ocean.jsonize(&self.config, &self.items),
-                Plain: Terrain::Plain.jsonize(&self.config, &self.items)},
+            trade_connections: self.trade_connections.iter().map(|(&(first, second), route)| {
+                let (first, second) = (self.config.grid().coord(first), self.config.grid().coord(second));
+                (format!("[{}, {}, {}, {}]", first.x, first.y, second.x, second.y), route.jsonize(&self.config, &self.items))
+            }).collect::<HashMap<String, JsonValue>>(),
+            trade_history: self.trade_history.jsonize(&self.config, &self.items),
+            trade_ledger: self.trade_ledger.jsonize(&self.config, &self.items),
+            Biomes: self.biomes.s_jsonize(),
+            Water: self.waters.jsonize(&self.config, &self.items),
             Items: self.items.s_jsonize(),
             Magic: self.magic.jsonize(&self.config, &self.items),
             current_year: self.current_year,
+            // u64 and u128 don't survive a trip through f64, so these are stored as strings
+            Seed: self.seed.to_string(),
+            RngState: self.rng.get_word_pos().to_string(),
             Config: self.config.jsonize(&self.config, &self.items)
         }
     }
@@ -380,47 +562,58 @@ impl SuperJsonizable for World {
         let Some(JsonValue::Array(arr)) = object.get("RegionList") else { return None; };
         if &json_string(object.get("file_type")?)? != "save" {
             return None;
-        };
+        }
         let config = Config::s_dejsonize(object.get("Config")?)?;
         let items = Items::s_dejsonize(object.get("Items")?)?;
-        let region_list: Vec<Region> = arr
+        // saves from before biomes were configurable used the fallbacks
+        let biomes = match object.get("Biomes") {
+            None => Biomes::default(),
+            Some(biomes) => Biomes::s_dejsonize(biomes)?,
+        };
+        // a region that can't be read, or whose terrain isn't one of the biomes, fails the load
+        // rather than shifting the ids of the rest or turning into the wrong land
+        let mut region_list: Vec<Region> = arr
             .iter()
             .enumerate()
-            .filter_map(|(id, region)| {
-                Region::dejsonize(region, &config, &items).map(|mut region| {
-                    region.set_id(id);
-                    region
-                })
+            .map(|(id, region)| {
+                let mut region = Region::dejsonize(region, &config, &items)?;
+                biomes.get(region.terrain())?;
+                region.set_id(id);
+                Some(region)
             })
-            .collect();
-        let trade_connections = {
-            let mut trade_connections = HashMap::new();
-            for (k, v) in tcons.iter() {
-                let key = {
-                    let Ok(JsonValue::Array(arr)) = json::parse(k) else { return None };
-                    (
-                        json_array_to_usize(
-                            &JsonValue::Array(vec![arr.get(0)?.clone(), arr.get(1)?.clone()]),
-                            &config,
-                        )?,
-                        json_array_to_usize(
-                            &JsonValue::Array(vec![arr.get(2)?.clone(), arr.get(3)?.clone()]),
-                            &config,
-                        )?,
-                    )
-                };
-                trade_connections.insert(key, json_int(v)?);
-            }
-            trade_connections
-        };
-        let mut region_map = vec![0; config.world_size.0 * config.world_size.1];
+            .collect::<Option<_>>()?;
+        let trade_connections = dejsonize_trade_connections(tcons, &config, &items)?;
+        let mut region_map = vec![0; config.grid().len()];
         for region in &region_list {
             for &tile in region.tiles() {
                 region_map[tile] = region.id();
             }
         }
+        // older saves stored a broken list of adjacent regions instead of borders
+        if arr.iter().any(|region| region["borders"].is_null()) {
+            Region::find_borders(&mut region_list, config.grid(), &biomes);
+        }
+        // saves from before seeding was supported get a fresh stream
+        let seed: u64 = object
+            .get("Seed")
+            .and_then(json_string)
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| thread_rng().gen());
+        let mut rng = sim_rng(seed);
+        if let Some(word_pos) = object
+            .get("RngState")
+            .and_then(json_string)
+            .and_then(|state| state.parse().ok())
+        {
+            rng.set_word_pos(word_pos);
+        }
+        let mut trade_connections_list: Vec<(usize, usize)> =
+            trade_connections.keys().copied().collect();
+        trade_connections_list.sort_unstable();
         Some(Self {
             config,
+            seed,
+            rng,
             magic: MagicSystem::dejsonize(object.get("Magic")?, &config, &items)?,
             current_year: json_int(object.get("current_year")?)? as u32,
             region_list,
@@ -428,26 +621,56 @@ impl SuperJsonizable for World {
                 .iter()
                 .map(|c| (c.pos(), c.clone()))
                 .collect(),
-            trade_connections_list: trade_connections.keys().copied().collect(),
+            trade_connections_list,
             trade_connections,
+            // older saves didn't keep track of how the network changed
+            trade_history: match object.get("trade_history") {
+                None => Vec::new(),
+                Some(history) => Vec::<RouteEvent>::dejsonize(history, &config, &items)?,
+            },
+            // or what was traded along it
+            trade_ledger: match object.get("trade_ledger") {
+                None => Vec::new(),
+                Some(ledger) => Vec::<Trade>::dejsonize(ledger, &config, &items)?,
+            },
+            waters: match object.get("Water") {
+                // saves from before rivers were generated are dry
+                None => Waters::new(config.grid().len()),
+                Some(waters) => Waters::dejsonize(waters, &config, &items)?,
+            },
             items,
+            biomes,
             region_map,
+            observers: Vec::new(),
         })
     }
 }
 
 impl World {
+    /// Load a save or generate a world from a gen file. A gen file is sampled with `seed`
+    /// (random if `None`); a save keeps its stored random stream unless `seed` is given.
+    #[must_use]
     pub fn from_file(
         src: &JsonValue,
-        rng: &mut ThreadRng,
+        seed: Option<u64>,
         markov: &MarkovCollection,
     ) -> Option<Self> {
         let JsonValue::Object(object) = src else {
             return None
         };
         match json_string(object.get("file_type")?)?.as_str() {
-            "gen" => Some(WorldGen::s_dejsonize(src)?.sample(rng, markov)),
-            "save" => Self::s_dejsonize(src),
+            "gen" => {
+                let seed = seed.unwrap_or_else(|| thread_rng().gen());
+                Some(WorldGen::s_dejsonize(src)?.sample(seed, markov))
+            }
+            "save" => {
+                let mut world = Self::s_dejsonize(src)?;
+                if let Some(seed) = seed {
+                    world.seed = seed;
+                    world.rng = sim_rng(seed);
+                }
+                Some(world)
+            }
             _ => None,
         }
     }
@@ -457,13 +680,7 @@ impl SuperJsonizable for WorldGen {
     fn s_jsonize(&self) -> JsonValue {
         json::object! {
             file_type: "gen",
-            Biomes: {
-                Desert: Terrain::Desert.jsonize(&self.config, &self.items),
-                Forest: Terrain::Forest.jsonize(&self.config, &self.items),
-                Jungle: Terrain::Jungle.jsonize(&self.config, &self.items),
-                Mountain: Terrain::Mountain.jsonize(&self.config, &self.items),
-                Ocean: Terrain::Ocean.jsonize(&self.config, &self.items),
-                Plain: Terrain::Plain.jsonize(&self.config, &self.items)},
+            Biomes: self.biomes.s_jsonize(),
             Config: self.config.s_jsonize(),
             Items: self.items_src.clone()
         }
@@ -475,7 +692,7 @@ impl SuperJsonizable for WorldGen {
         let Some(JsonValue::Array(items_src)) = object.get("Items") else { return None };
         if &json_string(object.get("file_type")?)? != "gen" {
             return None;
-        };
+        }
         let items_strings: Vec<String> = items_src.iter().filter_map(json_string).collect();
         let items_str: String = items_strings
             .iter()
@@ -491,6 +708,10 @@ impl SuperJsonizable for WorldGen {
         Some(Self {
             config: Config::s_dejsonize(object.get("Config")?)?,
             items,
+            biomes: match object.get("Biomes") {
+                None => Biomes::default(),
+                Some(biomes) => Biomes::s_dejsonize(biomes)?,
+            },
             items_src: items_strings,
         })
     }
@@ -0,0 +1,937 @@
+//! Procedurally generate a continent and simulate the history of its cities.
+
+#![warn(clippy::pedantic, clippy::nursery)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss,
+    clippy::cast_lossless,
+    clippy::format_collect
+)]
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    path::Path,
+};
+
+use json::{object, JsonValue};
+use magic::MagicSystem;
+use rand::{prelude::*, seq::SliceRandom, Rng};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use sim::{
+    forget_trades_before, handle_trade, notify, routes_from, Biomes, City, Grid, HistoricalEvent,
+    Inventory, Item, ItemIndex, ItemType, Observer, PriceOverrides, Pricing, Region, RouteChange,
+    RouteEvent, TickEvent, TileShape, Topology, Trade, TradeRoute, Waters,
+};
+use strum::{AsRefStr, EnumIter, IntoEnumIterator};
+
+pub mod sim;
+
+pub mod checkpoint;
+
+pub mod mkv;
+use mkv::{MarkovCollection, MarkovData};
+
+mod jsonize;
+pub use jsonize::{Jsonizable, SuperJsonizable};
+
+mod worldgen;
+
+pub mod magic;
+
+#[allow(unused_variables)]
+pub mod report;
+
+#[macro_export]
+macro_rules! mut_loop {
+    ($original_list: expr => for $item: ident in $list: ident $func: expr) => {
+        let mut $list = std::mem::take(&mut $original_list);
+        for _ in 0..$list.len() {
+            // unwrap is safe as long as $func doesn't mutate list
+            let $item = $list.pop().unwrap();
+            $func
+            $list.insert(0, $item);
+        }
+        $original_list = $list;
+    };
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EnumIter, AsRefStr)]
+pub enum Skill {
+    Leadership,
+    Metalworking,
+    Teaching,
+    Gemcutting,
+    Magic,
+    AnimalTraining,
+    Adventuring,
+    Philosophy,
+}
+
+#[derive(Debug, Clone)]
+pub struct Npc {
+    name: String,
+    title: String,
+    pos: usize,
+    origin: usize,
+    birth: u32,
+    age: u32,
+    alive: bool,
+    skills: HashMap<Skill, u8>,
+    life: Vec<HistoricalEvent>,
+}
+
+impl Npc {
+    #[must_use]
+    pub const fn name(&self) -> &String {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn title(&self) -> &String {
+        &self.title
+    }
+
+    #[must_use]
+    pub const fn pos(&self) -> usize {
+        self.pos
+    }
+
+    #[must_use]
+    pub const fn origin(&self) -> usize {
+        self.origin
+    }
+
+    #[must_use]
+    pub const fn birth(&self) -> u32 {
+        self.birth
+    }
+
+    #[must_use]
+    pub const fn age(&self) -> u32 {
+        self.age
+    }
+
+    #[must_use]
+    pub const fn alive(&self) -> bool {
+        self.alive
+    }
+
+    #[must_use]
+    pub fn skill(&self, skill: Skill) -> u8 {
+        self.skills.get(&skill).copied().unwrap_or_default()
+    }
+
+    #[must_use]
+    pub const fn life(&self) -> &Vec<HistoricalEvent> {
+        &self.life
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Config {
+    gen_radius: usize,
+    world_size: (usize, usize),
+    topology: Topology,
+    tile_shape: TileShape,
+    land_ratio: f32,
+    continents: usize,
+    archipelago_density: f32,
+    coastline_roughness: f32,
+    coastal_city_density: f32,
+    inland_city_density: f32,
+    city_count: Option<usize>,
+    city_spacing: f32,
+    production_constant: f32,
+    population_constant: f32,
+    mineral_depletion: f32,
+    /// How many metal goods, cut gems and tame animals each person uses up a year, before
+    /// wealth and culture
+    consumption: f32,
+    /// How far into debt each person lets their city go to pay for imports, in coins
+    credit: f32,
+    /// The share of its stock of the coin metal a city strikes into coins each year
+    minting: f32,
+    notable_npc_threshold: u8,
+    trade_volume: f32,
+    /// Only the python sim reads this, since it still trades a set number of times a year
+    trade_quantity: i32,
+    trade_reach: f32,
+    /// How much more established a route gets with each trade
+    route_growth: f32,
+    /// How much less established every route gets each year
+    route_decay: f32,
+    /// How much faster a route passing by a monster's lair fades
+    route_danger: f32,
+    /// The population at which a city looks for another partner, again every time it doubles
+    prosperity: i32,
+    snapshot_interval: u32,
+    /// How many years of trades the ledger keeps, since every year adds a trade or more for
+    /// each open route
+    ledger_years: u32,
+}
+
+impl Config {
+    #[must_use]
+    pub const fn world_size(&self) -> (usize, usize) {
+        self.world_size
+    }
+
+    #[must_use]
+    pub const fn grid(&self) -> Grid {
+        Grid::new(
+            self.world_size.0,
+            self.world_size.1,
+            self.topology,
+            self.tile_shape,
+        )
+    }
+
+    /// How many years pass between city snapshots
+    #[must_use]
+    pub const fn snapshot_interval(&self) -> u32 {
+        self.snapshot_interval
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gen_radius: 3,
+            world_size: (40, 30),
+            topology: Topology::Flat,
+            tile_shape: TileShape::Square,
+            land_ratio: 0.4,
+            continents: 1,
+            archipelago_density: 0.0,
+            coastline_roughness: 0.5,
+            coastal_city_density: 0.15,
+            inland_city_density: 0.02,
+            city_count: None,
+            city_spacing: 2.0,
+            production_constant: 60.0,
+            population_constant: 0.0001,
+            mineral_depletion: 0.00001,
+            consumption: 0.05,
+            credit: 1.0,
+            minting: 0.1,
+            notable_npc_threshold: 5,
+            trade_volume: 50.0,
+            trade_quantity: 20,
+            trade_reach: 5.0,
+            route_growth: 0.1,
+            route_decay: 0.02,
+            route_danger: 0.05,
+            prosperity: 500,
+            snapshot_interval: 100,
+            ledger_years: 100,
+        }
+    }
+}
+
+/// Every item in a world, with constant-time lookups between [`Item`]s and their [`ItemIndex`]
+#[derive(Clone)]
+pub struct Items {
+    all: Vec<Item>,
+    indices: HashMap<Item, ItemIndex>,
+    names: HashMap<String, ItemIndex>,
+    plants: Vec<ItemType>,
+    metals: Vec<ItemType>,
+    gems: Vec<ItemType>,
+    animals: Vec<ItemType>,
+    /// Worked out once for every item, by index
+    pricing: Vec<Pricing>,
+    coin_metal: Option<ItemIndex>,
+}
+
+impl Items {
+    fn from_item_types(
+        plants: Vec<ItemType>,
+        metals: Vec<ItemType>,
+        gems: Vec<ItemType>,
+        animals: Vec<ItemType>,
+    ) -> Self {
+        let mut all_items: Vec<Item> = vec![Item::Fish];
+        for plant in 0..plants.len() {
+            all_items.push(Item::Plant(plant as u8));
+        }
+        for metal in 0..metals.len() {
+            all_items.push(Item::Metal(metal as u8));
+            all_items.push(Item::MetalGood(metal as u8));
+        }
+        for gem in 0..gems.len() {
+            all_items.push(Item::Gem(gem as u8));
+            all_items.push(Item::CutGem(gem as u8));
+        }
+        for animal in 0..animals.len() {
+            all_items.push(Item::WildAnimal(animal as u8));
+            all_items.push(Item::TameAnimal(animal as u8));
+            all_items.push(Item::Meat(animal as u8));
+        }
+        let mut items = Self {
+            indices: all_items
+                .iter()
+                .enumerate()
+                .map(|(index, &item)| (item, ItemIndex::new(index)))
+                .collect(),
+            names: HashMap::new(),
+            all: all_items,
+            plants,
+            metals,
+            gems,
+            animals,
+            pricing: Vec::new(),
+            coin_metal: None,
+        };
+        items.pricing = items.all.iter().map(|item| item.pricing(&items)).collect();
+        // backwards, so if two items share a name the first one keeps it
+        items.names = items
+            .iter()
+            .rev()
+            .map(|(index, item)| (item.to_string(&items), index))
+            .collect();
+        // coins are struck from the most valuable metal
+        items.coin_metal = items
+            .iter()
+            .filter(|(_, item)| matches!(item, Item::Metal(_)))
+            .max_by(|(a, _), (b, _)| items.pricing(*a).base.total_cmp(&items.pricing(*b).base))
+            .map(|(index, _)| index);
+        items
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.all.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.all.is_empty()
+    }
+
+    /// The index of `item`, if it exists in this world
+    #[must_use]
+    pub fn index(&self, item: Item) -> Option<ItemIndex> {
+        self.indices.get(&item).copied()
+    }
+
+    /// The index of the item called `name`, if there is one
+    #[must_use]
+    pub fn named(&self, name: &str) -> Option<ItemIndex> {
+        self.names.get(name).copied()
+    }
+
+    /// The metal the world's coins are struck from, if there are any metals
+    #[must_use]
+    pub const fn coin_metal(&self) -> Option<ItemIndex> {
+        self.coin_metal
+    }
+
+    #[must_use]
+    pub fn pricing(&self, index: ItemIndex) -> Pricing {
+        self.pricing[index.get()]
+    }
+
+    #[must_use]
+    pub fn item(&self, index: ItemIndex) -> Item {
+        self.all[index.get()]
+    }
+
+    #[must_use]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (ItemIndex, Item)> + '_ {
+        self.all
+            .iter()
+            .enumerate()
+            .map(|(index, &item)| (ItemIndex::new(index), item))
+    }
+}
+
+/// The random stream a world is simulated with. It can be stored and resumed exactly.
+pub type SimRng = ChaCha8Rng;
+
+/// The stream a world seeded with `seed` is simulated on, apart from the one it was generated on
+fn sim_rng(seed: u64) -> SimRng {
+    let mut rng = SimRng::seed_from_u64(seed);
+    rng.set_stream(1);
+    rng
+}
+
+pub struct World {
+    config: Config,
+    current_year: u32,
+    seed: u64,
+    rng: SimRng,
+    region_map: Vec<usize>,
+    region_list: Vec<Region>,
+    city_list: BTreeMap<usize, City>,
+    trade_connections: HashMap<(usize, usize), TradeRoute>,
+    trade_connections_list: Vec<(usize, usize)>,
+    trade_history: Vec<RouteEvent>,
+    /// Every good bought along a trade route in the last `ledger_years` years, oldest first
+    trade_ledger: Vec<Trade>,
+    items: Items,
+    biomes: Biomes,
+    waters: Waters,
+    magic: MagicSystem,
+    observers: Vec<Box<dyn Observer>>,
+}
+
+impl World {
+    /// Read a gen or save file from disk. See [`World::from_file`].
+    pub fn load(
+        path: impl AsRef<Path>,
+        seed: Option<u64>,
+        markov: &MarkovCollection,
+    ) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        Self::from_file(&json::parse(&contents).ok()?, seed, markov)
+    }
+
+    /// Have `observer` notified of notable events in every following tick
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Simulate `years` years on the world's own random stream
+    pub fn step(&mut self, years: u32, markov: &MarkovCollection) {
+        let mut rng = self.rng.clone();
+        for _ in 0..years {
+            self.tick(&mut rng, &markov.name);
+        }
+        self.rng = rng;
+    }
+
+    /// Serialize the world into a save file
+    #[must_use]
+    pub fn save(&self) -> String {
+        self.s_jsonize().dump()
+    }
+
+    #[must_use]
+    pub const fn current_year(&self) -> u32 {
+        self.current_year
+    }
+
+    #[must_use]
+    pub const fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    #[must_use]
+    pub const fn items(&self) -> &Items {
+        &self.items
+    }
+
+    #[must_use]
+    pub const fn biomes(&self) -> &Biomes {
+        &self.biomes
+    }
+
+    #[must_use]
+    pub const fn waters(&self) -> &Waters {
+        &self.waters
+    }
+
+    #[must_use]
+    pub const fn magic(&self) -> &MagicSystem {
+        &self.magic
+    }
+
+    pub fn cities(&self) -> impl Iterator<Item = &City> {
+        self.city_list.values()
+    }
+
+    #[must_use]
+    pub fn city(&self, pos: usize) -> Option<&City> {
+        self.city_list.get(&pos)
+    }
+
+    /// The open trade route between the cities at `first` and `second`, either way round
+    #[must_use]
+    pub fn trade_route(&self, first: usize, second: usize) -> Option<&TradeRoute> {
+        self.trade_connections
+            .get(&(first.min(second), first.max(second)))
+    }
+
+    /// Every good bought along a trade route in the years the ledger keeps, oldest first
+    #[must_use]
+    pub fn trade_ledger(&self) -> &[Trade] {
+        &self.trade_ledger
+    }
+
+    /// Every good in the ledger the cities at `first` and `second` bought from each other
+    pub fn trades_between(&self, first: usize, second: usize) -> impl Iterator<Item = &Trade> {
+        let route = (first.min(second), first.max(second));
+        self.trade_ledger
+            .iter()
+            .filter(move |trade| trade.route == route)
+    }
+
+    #[must_use]
+    pub const fn regions(&self) -> &Vec<Region> {
+        &self.region_list
+    }
+
+    /// The region that the tile at `pos` belongs to
+    #[must_use]
+    pub fn region_at(&self, pos: usize) -> Option<&Region> {
+        self.region_list.get(*self.region_map.get(pos)?)
+    }
+
+    /// The shortest chain of bordering land regions from `from` to `to`, both included. `None`
+    /// if there's no way across land.
+    #[must_use]
+    pub fn region_path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let passable = |id: usize| {
+            self.region_list
+                .get(id)
+                .is_some_and(|region| self.biomes[region.terrain()].passable)
+        };
+        if !passable(from) || !passable(to) {
+            return None;
+        }
+        let mut previous = vec![None; self.region_list.len()];
+        previous[from] = Some(from);
+        let mut queue = VecDeque::from([from]);
+        while let Some(id) = queue.pop_front() {
+            if id == to {
+                let mut path = vec![to];
+                while let Some(&last) = path.last().filter(|&&last| last != from) {
+                    path.push(previous[last]?);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for &next in self.region_list[id].borders().keys() {
+                if previous[next].is_none() && passable(next) {
+                    previous[next] = Some(id);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Every NPC in every city, living or dead
+    pub fn npcs(&self) -> impl Iterator<Item = &Npc> {
+        self.city_list.values().flat_map(City::npcs)
+    }
+
+    /// Advance the world by one year, drawing from `rng`
+    pub fn tick<R: Rng + ?Sized>(&mut self, rng: &mut R, markov_data_npc: &MarkovData) {
+        // Cities only touch their own state, so they can tick in parallel. Each one gets its
+        // own stream of this year's key, so the result doesn't depend on thread scheduling.
+        let year_seed: u64 = rng.gen();
+        let passable: Vec<bool> = self
+            .region_map
+            .iter()
+            .map(|&region| self.biomes[self.region_list[region].terrain()].passable)
+            .collect();
+        // what the cities had before the year, to tell which of them grow prosperous
+        let populations: Vec<(usize, i32)> = self
+            .city_list
+            .values()
+            .map(|city| (city.pos(), city.population()))
+            .collect();
+        self.city_list.par_iter_mut().for_each(|(&pos, city)| {
+            let mut city_rng = SimRng::seed_from_u64(year_seed);
+            city_rng.set_stream(pos as u64);
+            city.tick(
+                &mut city_rng,
+                self.current_year,
+                &self.config,
+                &self.items,
+                &self.magic,
+                markov_data_npc,
+                &self.observers,
+                &passable,
+            );
+        });
+        self.update_trade_network(&populations);
+        // Trades touch two cities at once, so they're resolved serially afterwards, every route
+        // in turn. Shuffled so no city always gets first pick of its partners' goods.
+        let mut routes = self.trade_connections_list.clone();
+        routes.shuffle(rng);
+        for route in routes {
+            let trades = handle_trade(
+                route,
+                &mut self.city_list,
+                &mut self.trade_connections,
+                self.current_year,
+                &self.config,
+                &self.items,
+                &self.observers,
+            );
+            self.trade_ledger.extend(trades);
+        }
+        self.current_year += 1;
+        let oldest = self.current_year.saturating_sub(self.config.ledger_years);
+        forget_trades_before(&mut self.trade_ledger, oldest);
+    }
+
+    /// Whether the city at `pos` is still alive
+    fn is_living(&self, pos: usize) -> bool {
+        self.city_list
+            .get(&pos)
+            .is_some_and(|city| city.population() > 0)
+    }
+
+    /// Let the trade network change over a year. `populations` are the cities' populations at
+    /// the start of it.
+    fn update_trade_network(&mut self, populations: &[(usize, i32)]) {
+        let grid = self.config.grid();
+        // tiles at most a step from a monster's lair
+        let lairs: HashSet<usize> = self
+            .region_list
+            .iter()
+            .filter_map(Region::lair)
+            .flat_map(|lair| grid.neighbors(lair, 1).into_iter().chain([lair]))
+            .collect();
+        self.close_routes(&lairs);
+        self.open_routes(populations, &lairs);
+        self.trade_connections_list = self.trade_connections.keys().copied().collect();
+        self.trade_connections_list.sort_unstable();
+    }
+
+    /// Fade every trade route, faster if it passes by `lairs`, and close the ones that run out
+    /// or lost a city
+    fn close_routes(&mut self, lairs: &HashSet<usize>) {
+        let mut closed = Vec::new();
+        for &route in &self.trade_connections_list {
+            let ruined = !self.is_living(route.0) || !self.is_living(route.1);
+            let Some(trade_route) = self.trade_connections.get_mut(&route) else { continue };
+            let dangerous = trade_route.path.iter().any(|tile| lairs.contains(tile));
+            trade_route.strength -= self.config.route_decay;
+            if dangerous {
+                trade_route.strength -= self.config.route_danger;
+            }
+            if ruined {
+                closed.push((route, RouteChange::Ruin));
+            } else if trade_route.strength <= 0.0 && dangerous {
+                closed.push((route, RouteChange::Danger));
+            } else if trade_route.strength <= 0.0 {
+                closed.push((route, RouteChange::Disuse));
+            }
+        }
+        for (route, change) in closed {
+            self.trade_connections.remove(&route);
+            self.record_route_change(route, change);
+        }
+    }
+
+    /// Open routes for cities with no partners or that grew prosperous, and between cities
+    /// that travelers found. Only travelers open routes that pass by `lairs`.
+    fn open_routes(&mut self, populations: &[(usize, i32)], lairs: &HashSet<usize>) {
+        let grid = self.config.grid();
+        let partners: HashSet<usize> = self
+            .trade_connections
+            .keys()
+            .flat_map(|&route| <[usize; 2]>::from(route))
+            .collect();
+        // cities become prosperous at a population of `prosperity`, and again each time it
+        // doubles
+        let prosperity = |population: i32| {
+            (population >= self.config.prosperity)
+                .then(|| (population / self.config.prosperity).ilog2())
+        };
+        let mut opened = Vec::new();
+        for &(pos, old) in populations {
+            let Some(city) = self.city_list.get(&pos).filter(|_| self.is_living(pos)) else {
+                continue;
+            };
+            if !partners.contains(&pos) {
+                opened.push((pos, None, RouteChange::Isolation));
+            } else if prosperity(city.population()) > prosperity(old) {
+                opened.push((pos, None, RouteChange::Prosperity));
+            }
+        }
+        // a traveler who wanders up to another city brings the two together
+        for npc in self
+            .npcs()
+            .filter(|npc| npc.alive() && npc.pos() != npc.origin())
+        {
+            for tile in grid.neighbors(npc.pos(), 1).into_iter().chain([npc.pos()]) {
+                let route = (tile.min(npc.origin()), tile.max(npc.origin()));
+                if tile != npc.origin()
+                    && self.is_living(tile)
+                    && !self.trade_connections.contains_key(&route)
+                {
+                    opened.push((npc.origin(), Some(tile), RouteChange::Discovery));
+                }
+            }
+        }
+        for (pos, partner, change) in opened {
+            self.open_route(pos, partner, change, lairs);
+        }
+    }
+
+    /// Open the cheapest route within reach from the city at `pos` to `partner`, or to any
+    /// living city it doesn't trade with yet by a path clear of `lairs`. Nothing happens if
+    /// there's none.
+    fn open_route(
+        &mut self,
+        pos: usize,
+        partner: Option<usize>,
+        change: RouteChange,
+        lairs: &HashSet<usize>,
+    ) {
+        let key = |end: usize| (pos.min(end), pos.max(end));
+        let routes = routes_from(
+            self.config.grid(),
+            pos,
+            |tile| &self.biomes[self.region_list[self.region_map[tile]].terrain()],
+            &self.waters,
+            |tile| self.is_living(tile),
+            self.config.trade_reach,
+        );
+        let Some((end, route)) = routes
+            .into_iter()
+            .filter(|&(end, _)| partner.is_none_or(|partner| partner == end))
+            .filter(|(_, route)| {
+                partner.is_some() || !route.path.iter().any(|tile| lairs.contains(tile))
+            })
+            .filter(|&(end, _)| !self.trade_connections.contains_key(&key(end)))
+            // a route that just closed doesn't open again the same year
+            .filter(|&(end, _)| {
+                !self
+                    .trade_history
+                    .iter()
+                    .rev()
+                    .take_while(|event| event.year == self.current_year)
+                    .any(|event| event.route == key(end))
+            })
+            .min_by(|(_, a), (_, b)| a.cost.total_cmp(&b.cost))
+        else {
+            return;
+        };
+        self.trade_connections.insert(key(end), route);
+        self.record_route_change(key(end), change);
+    }
+
+    fn record_route_change(&mut self, route: (usize, usize), change: RouteChange) {
+        notify(
+            &self.observers,
+            self.current_year,
+            &TickEvent::RouteChanged { route, change },
+        );
+        self.trade_history.push(RouteEvent {
+            year: self.current_year,
+            route,
+            change,
+        });
+    }
+}
+
+pub struct WorldGen {
+    config: Config,
+    items: Items,
+    biomes: Biomes,
+    items_src: Vec<String>,
+}
+
+impl MarkovCollection {
+    /// The name generators that `build.rs` compiled from the csv files
+    #[must_use]
+    pub fn builtin() -> Self {
+        macro_rules! mkv {
+            {$path: expr} => {{
+                    MarkovData::from_bytes(include_bytes!(concat!("..\\markov\\", $path, ".mkv"))).unwrap()
+                }
+            }
+        }
+
+        Self {
+            gem: mkv!("gemstone"),
+            magic: mkv!("magic"),
+            metal: mkv!("metal"),
+            monster: mkv!("monster"),
+            name: mkv!("name"),
+            plant: mkv!("plant"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::JsonValue;
+
+    use super::{
+        mkv::MarkovCollection,
+        sim::{Biomes, City},
+        Item, Items, Region, SuperJsonizable, World,
+    };
+
+    /// The tiny gen file, to change before generating from it
+    fn tiny_gen() -> JsonValue {
+        json::parse(&std::fs::read_to_string("json_data/tiny.json").unwrap()).unwrap()
+    }
+
+    /// The world the tiny gen file makes from seed 7
+    fn tiny(markov: &MarkovCollection) -> World {
+        World::from_file(&tiny_gen(), Some(7), markov).unwrap()
+    }
+
+    #[test]
+    fn seeded_runs_match() {
+        let markov = MarkovCollection::builtin();
+        let mut first = tiny(&markov);
+        let mut second = tiny(&markov);
+        first.step(20, &markov);
+        second.step(20, &markov);
+        // the save keeps the seed the run was started with, so it can be started over
+        assert_eq!(first.seed(), 7);
+        assert_eq!(
+            json::parse(&first.save()).unwrap(),
+            json::parse(&second.save()).unwrap()
+        );
+    }
+
+    #[test]
+    fn resumed_run_matches() {
+        let markov = MarkovCollection::builtin();
+        let mut straight = tiny(&markov);
+        straight.step(20, &markov);
+        let mut resumed = tiny(&markov);
+        resumed.step(10, &markov);
+        let mut resumed =
+            World::from_file(&json::parse(&resumed.save()).unwrap(), None, &markov).unwrap();
+        resumed.step(10, &markov);
+        assert_eq!(
+            json::parse(&straight.save()).unwrap(),
+            json::parse(&resumed.save()).unwrap()
+        );
+    }
+
+    #[test]
+    fn saves_keep_what_the_python_sim_reads() {
+        let markov = MarkovCollection::builtin();
+        let world = tiny(&markov);
+        let save = json::parse(&world.save()).unwrap();
+        assert_eq!(save["Config"]["TRADE_QUANTITY"], 20);
+    }
+
+    #[test]
+    fn item_indices_round_trip() {
+        let markov = MarkovCollection::builtin();
+        let world = tiny(&markov);
+        let items = world.items();
+        for (index, item) in items.iter() {
+            assert_eq!(items.index(item), Some(index));
+            assert_eq!(items.item(index), item);
+            let named = items.named(&item.to_string(items)).unwrap();
+            assert_eq!(items.item(named).to_string(items), item.to_string(items));
+        }
+        assert_eq!(items.index(Item::Plant(u8::MAX)), None);
+        assert_eq!(items.named("Unobtainium"), None);
+        let magic = world.magic().index.unwrap();
+        assert_eq!(
+            items.item(magic).to_string(items),
+            world.magic().material.name
+        );
+    }
+
+    #[test]
+    fn biomes_come_from_the_gen_file() {
+        let markov = MarkovCollection::builtin();
+        let world = tiny(&markov);
+        let gen = tiny_gen();
+        let biomes = Biomes::s_dejsonize(&gen["Biomes"]).unwrap();
+        assert_eq!(world.biomes(), &biomes);
+        assert_eq!(biomes["Desert"].color, [160, 140, 90]);
+        let mut save = json::parse(&world.save()).unwrap();
+        assert!(save["Biomes"].has_key("Sea"));
+        assert_eq!(Biomes::s_dejsonize(&save["Biomes"]).unwrap(), biomes);
+        // a region of a biome the save doesn't have fails the load
+        save["RegionList"][1]["terrain"] = "Plian".into();
+        assert!(World::from_file(&save, None, &markov).is_none());
+    }
+
+    #[test]
+    fn gen_files_can_add_biomes() {
+        let markov = MarkovCollection::builtin();
+        let mut gen = tiny_gen();
+        gen["Biomes"]["Tundra"] = json::object! {
+            Resources: { Animal: 0.2 },
+            Monsters: ["Beast"],
+            Color: [200, 210, 220]
+        };
+        let world = World::from_file(&gen, Some(7), &markov).unwrap();
+        let tundra = &world.biomes()["Tundra"];
+        assert_eq!((tundra.glyph, tundra.passable), ('T', true));
+        assert!(!world.biomes()["Sea"].passable);
+        assert!(world.cities().all(|city| {
+            let region = world.region_at(city.pos()).unwrap();
+            world.biomes()[region.terrain()].passable
+        }));
+    }
+
+    #[test]
+    fn region_paths_follow_borders() {
+        let markov = MarkovCollection::builtin();
+        let world = tiny(&markov);
+        let land: Vec<usize> = world
+            .regions()
+            .iter()
+            .filter(|region| !region.climate().is_water())
+            .map(Region::id)
+            .collect();
+        let path = world.region_path(land[0], land[1]).unwrap();
+        assert_eq!((path[0], path[path.len() - 1]), (land[0], land[1]));
+        for pair in path.windows(2) {
+            assert!(world.regions()[pair[0]].border(pair[1]).is_some());
+        }
+    }
+
+    #[test]
+    fn bad_price_overrides_fail_the_load() {
+        let gems = |glass: &str| {
+            json::parse(&format!(
+                r#"{{"Plants": {{}}, "Metals": {{}}, "Animals": {{}}, "Gems": {{"Glass": {glass}, "Quartz": [1, 1, 2]}}}}"#
+            ))
+            .unwrap()
+        };
+        assert!(Items::s_dejsonize(&gems(r#"[1, 1, 2, {"price": 7}]"#)).is_some());
+        // dropping Glass would quietly turn Quartz into item 0
+        assert!(Items::s_dejsonize(&gems(r#"[1, 1, 2, {"cost": 7}]"#)).is_none());
+        assert!(Items::s_dejsonize(&gems(r#"[1, 1, 2, {"price": "lots"}]"#)).is_none());
+    }
+
+    #[test]
+    fn wants_save_beside_cultural_values() {
+        let markov = MarkovCollection::builtin();
+        let world = tiny(&markov);
+        let save = json::parse(&world.save()).unwrap();
+        for city in save["CityList"].members() {
+            assert!(city["wants"].has_key("Goods"));
+            // the python sim fills cultural_values in with its own
+            assert!(city["cultural_values"].is_empty());
+        }
+        let saved = World::from_file(&save, None, &markov).unwrap();
+        for city in world.cities() {
+            assert_eq!(saved.city(city.pos()).unwrap().culture(), city.culture());
+        }
+    }
+
+    #[test]
+    fn trade_moves_coins_between_cities() {
+        let markov = MarkovCollection::builtin();
+        let mut gen = tiny_gen();
+        // with no coins struck, trading only passes coins from buyer to seller
+        gen["Config"]["MINTING"] = 0.into();
+        let mut world = World::from_file(&gen, Some(7), &markov).unwrap();
+        let total = |world: &World| world.cities().map(City::treasury).sum::<f32>();
+        let before = total(&world);
+        world.step(30, &markov);
+        let spent: f32 = world.cities().map(City::spent).sum();
+        assert!(spent > 0.0);
+        assert!((total(&world) - before).abs() / before < 1e-3);
+    }
+}
@@ -1,10 +1,8 @@
-use rand::{
-    distributions::Standard, prelude::Distribution, rngs::ThreadRng, seq::IteratorRandom, Rng,
-};
+use rand::{distributions::Standard, prelude::Distribution, seq::IteratorRandom, Rng};
 use strum::IntoEnumIterator;
 use strum_macros::{AsRefStr, EnumIter};
 
-use crate::{mkv::MarkovCollection, ItemType};
+use crate::{mkv::MarkovCollection, sim::PriceOverrides, ItemIndex, ItemType};
 
 #[derive(EnumIter, Debug, PartialEq, Eq, Clone, Copy, AsRefStr)]
 pub enum MaterialType {
@@ -22,6 +20,7 @@ pub enum AbilityType {
 }
 
 #[derive(Debug, Clone, Copy)]
+#[allow(clippy::struct_field_names)]
 pub struct Ability {
     pub ability_type: AbilityType,
     pub strength: u8,
@@ -62,11 +61,14 @@ pub struct MagicSystem {
     pub material_type: MaterialType,
     pub name: String,
     pub abilities: Vec<Ability>,
-    pub index: Option<usize>,
+    pub index: Option<ItemIndex>,
 }
 
 impl MagicSystem {
-    pub fn gen(rng: &mut ThreadRng, markov: &MarkovCollection) -> Self {
+    /// # Panics
+    ///
+    /// Never, since there's always a material type to pick
+    pub fn gen<R: Rng + ?Sized>(rng: &mut R, markov: &MarkovCollection) -> Self {
         let material_type = MaterialType::iter().choose(rng).unwrap();
         let material_rarity = rng.gen_range(6..10);
         Self {
@@ -83,6 +85,7 @@ impl MagicSystem {
                 abundance: 2,
                 value: 10,
                 taming: 0,
+                overrides: PriceOverrides::default(),
             },
             name: markov.magic.sample(rng),
             abilities: (0..3).map(|_| rng.gen()).collect(),
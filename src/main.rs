@@ -1,203 +1,21 @@
 #![warn(clippy::pedantic, clippy::nursery)]
-#![allow(
-    clippy::cast_possible_truncation,
-    clippy::cast_possible_wrap,
-    clippy::cast_precision_loss,
-    clippy::cast_sign_loss,
-    clippy::cast_lossless
-)]
 
 use std::{
-    collections::HashMap,
     env,
     ffi::OsStr,
     fs,
     io::{self, Write},
+    path::PathBuf,
 };
 
 use clap::{Parser, Subcommand};
-use json::{object, JsonValue};
-use magic::MagicSystem;
-use rand::{prelude::*, seq::SliceRandom, Rng};
-use sim::{handle_trade, City, HistoricalEvent, Inventory, Item, ItemType, Region};
-// use rayon::prelude::*;
-use strum::{AsRefStr, EnumIter, IntoEnumIterator};
-
-mod sim;
-
-mod mkv;
-use mkv::{MarkovCollection, MarkovData};
-
-mod jsonize;
-use jsonize::SuperJsonizable;
-
-use crate::sim::Terrain;
-
-mod worldgen;
-
-mod magic;
-
-#[allow(unused_variables)]
-mod report;
-
-#[macro_export]
-macro_rules! mut_loop {
-    ($original_list: expr => for $item: ident in $list: ident $func: expr) => {
-        let mut $list = std::mem::take(&mut $original_list);
-        for _ in 0..$list.len() {
-            // unwrap is safe as long as $func doesn't mutate list
-            let $item = $list.pop().unwrap();
-            $func
-            $list.insert(0, $item);
-        }
-        $original_list = $list;
-    };
-}
-
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EnumIter, AsRefStr)]
-pub enum Skill {
-    Leadership,
-    Metalworking,
-    Teaching,
-    Gemcutting,
-    Magic,
-    AnimalTraining,
-    Adventuring,
-    Philosophy,
-}
-
-#[derive(Debug, Clone)]
-pub struct Npc {
-    name: String,
-    title: String,
-    pos: usize,
-    origin: usize,
-    birth: u32,
-    age: u32,
-    alive: bool,
-    skills: HashMap<Skill, u8>,
-    life: Vec<HistoricalEvent>,
-}
-
-#[derive(Clone, Copy)]
-pub struct Config {
-    gen_radius: usize,
-    world_size: (usize, usize),
-    coastal_city_density: f32,
-    inland_city_density: f32,
-    production_constant: f32,
-    population_constant: f32,
-    mineral_depletion: f32,
-    notable_npc_threshold: u8,
-    trade_volume: f32,
-    trade_quantity: i32,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            gen_radius: 3,
-            world_size: (40, 30),
-            coastal_city_density: 0.15,
-            inland_city_density: 0.02,
-            production_constant: 60.0,
-            population_constant: 0.0001,
-            mineral_depletion: 0.00001,
-            notable_npc_threshold: 5,
-            trade_volume: 50.0,
-            trade_quantity: 20,
-        }
-    }
-}
-
-#[derive(Clone)]
-pub struct Items {
-    all: Vec<Item>,
-    plants: Vec<ItemType>,
-    metals: Vec<ItemType>,
-    gems: Vec<ItemType>,
-    animals: Vec<ItemType>,
-}
-
-impl Items {
-    fn from_item_types(
-        plants: Vec<ItemType>,
-        metals: Vec<ItemType>,
-        gems: Vec<ItemType>,
-        animals: Vec<ItemType>,
-    ) -> Self {
-        let mut all_items: Vec<Item> = vec![Item::Fish];
-        for plant in 0..plants.len() {
-            all_items.push(Item::Plant(plant as u8));
-        }
-        for metal in 0..metals.len() {
-            all_items.push(Item::Metal(metal as u8));
-            all_items.push(Item::MetalGood(metal as u8));
-        }
-        for gem in 0..gems.len() {
-            all_items.push(Item::Gem(gem as u8));
-            all_items.push(Item::CutGem(gem as u8));
-        }
-        for animal in 0..animals.len() {
-            all_items.push(Item::WildAnimal(animal as u8));
-            all_items.push(Item::TameAnimal(animal as u8));
-            all_items.push(Item::Meat(animal as u8));
-        }
-        Self {
-            all: all_items,
-            plants,
-            metals,
-            gems,
-            animals,
-        }
-    }
-}
-
-pub struct World {
-    config: Config,
-    current_year: u32,
-    region_map: Vec<usize>,
-    region_list: Vec<Region>,
-    city_list: HashMap<usize, City>,
-    trade_connections: HashMap<(usize, usize), i32>,
-    trade_connections_list: Vec<(usize, usize)>,
-    items: Items,
-    magic: MagicSystem,
-}
-
-impl World {
-    fn tick(&mut self, rng: &mut ThreadRng, markov_data_npc: &MarkovData) {
-        for city in self.city_list.values_mut() {
-            city.tick(
-                rng,
-                self.current_year,
-                &self.config,
-                &self.items,
-                &self.magic,
-                markov_data_npc,
-            );
-        }
-        for _ in 0..self.config.trade_quantity {
-            let _ = handle_trade(
-                match self.trade_connections_list.choose(rng) {
-                    Some(&res) => res,
-                    None => continue,
-                },
-                &mut self.city_list,
-                &mut self.trade_connections,
-                &self.config,
-                &self.items,
-            );
-        }
-        self.current_year += 1;
-    }
-}
-
-struct WorldGen {
-    config: Config,
-    items: Items,
-    items_src: Vec<String>,
-}
+use continent::{
+    checkpoint::Checkpoints,
+    mkv::MarkovCollection,
+    report,
+    sim::{Coord, TileShape, Topology, Water},
+    SuperJsonizable, World, WorldGen,
+};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -227,25 +45,80 @@ enum Commands {
         /// Report to save to (doesn't report by default)
         #[arg(short, long)]
         report: Option<String>,
+
+        /// Seed for the random number generator (saves resume their own stream by default)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        #[command(flatten)]
+        checkpoints: CheckpointArgs,
     },
+    /// Resume the simulation from the most recently written checkpoint
+    Resume {
+        /// Duration in years, counted from the checkpoint
+        #[arg(short, long, default_value_t = 1000)]
+        duration: u32,
+
+        /// Resume the run started with this seed (the most recently written run by default)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Checkpoint file to resume from, instead of looking in the checkpoint directory
+        #[arg(long, conflicts_with = "seed")]
+        checkpoint: Option<String>,
+
+        /// File to save to (doesn't save by default)
+        #[arg(short, long)]
+        save: Option<String>,
+
+        /// Report to save to (doesn't report by default)
+        #[arg(short, long)]
+        report: Option<String>,
+
+        #[command(flatten)]
+        checkpoints: CheckpointArgs,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckpointArgs {
+    /// Write a checkpoint every this many years (doesn't checkpoint by default)
+    #[arg(long)]
+    checkpoint_every: Option<u32>,
+
+    /// Number of checkpoints to keep
+    #[arg(long, default_value_t = 3)]
+    keep_checkpoints: usize,
+
+    /// Directory to write checkpoints to and resume from
+    #[arg(long, default_value = "checkpoints")]
+    checkpoint_dir: String,
+}
+
+impl CheckpointArgs {
+    fn checkpoints(&self) -> Option<Checkpoints> {
+        self.checkpoint_every
+            .map(|every| Checkpoints::new(&self.checkpoint_dir, every, self.keep_checkpoints))
+    }
 }
 
 fn cmd_run(
-    rng: &mut ThreadRng,
     markov: &MarkovCollection,
+    mut world: World,
     duration: u32,
-    path: String,
     save: Option<String>,
     report: Option<String>,
+    checkpoints: &CheckpointArgs,
 ) {
-    let Ok(contents) = fs::read_to_string(path) else { return };
-    let Ok(src) = json::parse(&contents) else { return };
-    let Some(mut world) = World::from_file(&src, rng, markov) else { return };
-
-    simulate_world(&mut world, rng, markov, duration);
+    simulate_world(
+        &mut world,
+        markov,
+        duration,
+        checkpoints.checkpoints().as_ref(),
+    );
 
     if let Some(savefile) = save {
-        fs::write(savefile, world.s_jsonize().dump()).expect("Unable to write file");
+        fs::write(savefile, world.save()).expect("Unable to write file");
     }
     if let Some(reportpath) = report {
         fs::write(reportpath, report::report(&world)).expect("Unable to write report");
@@ -254,32 +127,39 @@ fn cmd_run(
 
 fn simulate_world(
     world: &mut World,
-    rng: &mut ThreadRng,
     markov: &MarkovCollection,
     duration: u32,
+    checkpoints: Option<&Checkpoints>,
 ) {
     let year_delimiter: u32 = (duration / 100).max(1);
+    let grid = world.config().grid();
 
-    for y in 0..world.config.world_size.1 {
-        for x in 0..world.config.world_size.0 {
-            print!(
-                "{}",
-                match world.region_list[world.region_map[world.config.world_size.0 * y + x]]
-                    .terrain()
-                {
-                    Terrain::Ocean => "\x1b[48;5;18m~",
-                    Terrain::Plain => "\x1b[48;5;100m%",
-                    Terrain::Forest => "\x1b[48;5;22m♧",
-                    Terrain::Mountain => "\x1b[48;5;8m◮",
-                    Terrain::Desert => "\x1b[48;5;214m#",
-                    Terrain::Jungle => "\x1b[48;5;34m♤",
+    for y in 0..grid.height() {
+        // odd rows of hexes sit half a tile to the east
+        if grid.shape() == TileShape::Hex && y % 2 == 1 {
+            print!(" ");
+        }
+        for x in 0..grid.width() {
+            let Some(index) = grid.index(Coord::new(x, y)) else { continue };
+            let Some(region) = world.region_at(index) else { continue };
+            let biome = &world.biomes()[region.terrain()];
+            match world.waters().get(index) {
+                None => {
+                    let [r, g, b] = biome.color;
+                    print!("\x1b[48;2;{r};{g};{b}m{}", biome.glyph);
                 }
-            );
-            if world
-                .city_list
-                .iter()
-                .any(|(&pos, _c)| pos == x + y * world.config.world_size.0)
-            {
+                // rivers run through the land, lakes cover it
+                Some(Water::River) => {
+                    let [r, g, b] = biome.color;
+                    let [fr, fg, fb] = Water::River.color();
+                    print!("\x1b[48;2;{r};{g};{b};38;2;{fr};{fg};{fb}m~");
+                }
+                Some(Water::Lake) => {
+                    let [r, g, b] = Water::Lake.color();
+                    print!("\x1b[48;2;{r};{g};{b}m~");
+                }
+            }
+            if world.city(index).is_some() {
                 print!("O\x1b[0m");
             } else {
                 print!(" \x1b[0m");
@@ -287,6 +167,11 @@ fn simulate_world(
         }
         println!();
     }
+    match grid.topology() {
+        Topology::Flat => {}
+        Topology::Cylinder => println!("(the map wraps around east to west)"),
+        Topology::Torus => println!("(the map wraps around east to west and north to south)"),
+    }
     println!(
         "{}",
         String::from("╔")
@@ -302,7 +187,10 @@ fn simulate_world(
             print!("\x1b[32m\x1b[C█\x1b[D\x1b[0m");
             std::io::stdout().flush().unwrap();
         }
-        world.tick(rng, &markov.name);
+        world.step(1, markov);
+        if let Some(Err(err)) = checkpoints.map(|checkpoints| checkpoints.tick(world)) {
+            eprintln!("Unable to write checkpoint: {err}");
+        }
     }
 }
 
@@ -338,7 +226,7 @@ impl Iterator for WorldFinder {
                     .unwrap_or_default()
                     == "json"
             {
-                let Ok(text) = fs::read_to_string(&entry.path()) else { continue };
+                let Ok(text) = fs::read_to_string(entry.path()) else { continue };
                 let Ok(src) = json::parse(&text) else { continue };
                 if World::s_dejsonize(&src).is_some() || WorldGen::s_dejsonize(&src).is_some() {
                     return Some(entry);
@@ -351,23 +239,8 @@ impl Iterator for WorldFinder {
 
 fn main() {
     let args: Args = Args::parse();
-    let mut rng = thread_rng();
 
-    macro_rules! mkv {
-        {$path: expr} => {{
-                MarkovData::from_bytes(include_bytes!(concat!("..\\markov\\", $path, ".mkv"))).unwrap()
-            }
-        }
-    }
-
-    let mkv: mkv::MarkovCollection = mkv::MarkovCollection {
-        gem: mkv!("gemstone"),
-        magic: mkv!("magic"),
-        metal: mkv!("metal"),
-        monster: mkv!("monster"),
-        name: mkv!("name"),
-        plant: mkv!("plant"),
-    };
+    let mkv = MarkovCollection::builtin();
 
     match args.command {
         Commands::List => WorldFinder::new()
@@ -378,6 +251,42 @@ fn main() {
             path,
             save,
             report,
-        } => cmd_run(&mut rng, &mkv, duration, path, save, report),
+            seed,
+            checkpoints,
+        } => {
+            let Some(world) = World::load(&path, seed, &mkv) else {
+                eprintln!("Unable to load {path}");
+                return;
+            };
+            cmd_run(&mkv, world, duration, save, report, &checkpoints);
+        }
+        Commands::Resume {
+            duration,
+            seed,
+            checkpoint,
+            save,
+            report,
+            checkpoints,
+        } => {
+            let Some(path) = checkpoint
+                .map(PathBuf::from)
+                .or_else(|| Checkpoints::latest(&checkpoints.checkpoint_dir, seed))
+            else {
+                match seed {
+                    Some(seed) => eprintln!(
+                        "No checkpoints for seed {seed} in {}",
+                        checkpoints.checkpoint_dir
+                    ),
+                    None => eprintln!("No checkpoints in {}", checkpoints.checkpoint_dir),
+                }
+                return;
+            };
+            println!("Resuming from {}", path.display());
+            let Some(world) = World::load(&path, None, &mkv) else {
+                eprintln!("Unable to load {}", path.display());
+                return;
+            };
+            cmd_run(&mkv, world, duration, save, report, &checkpoints);
+        }
     }
 }
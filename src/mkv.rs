@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fs};
+use std::{collections::BTreeMap, fs};
 
 use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom};
 
@@ -6,7 +6,7 @@ use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom
 #[allow(clippy::type_complexity)]
 pub struct MarkovData {
     starts: Vec<(char, char)>,
-    map: HashMap<(char, char), (Vec<char>, Vec<u32>, WeightedIndex<u32>)>,
+    map: BTreeMap<(char, char), (Vec<char>, Vec<u32>, WeightedIndex<u32>)>,
 }
 
 #[allow(dead_code)]
@@ -106,7 +106,7 @@ impl MarkovData {
 
     pub fn from_strings(strings: &[&str]) -> Self {
         {
-            let mut counts: HashMap<((char, char), char), u32> = HashMap::new();
+            let mut counts: BTreeMap<((char, char), char), u32> = BTreeMap::new();
             let mut starts = Vec::new();
             for &string_mixedcase in strings {
                 let string = string_mixedcase.to_lowercase();
@@ -146,8 +146,8 @@ impl MarkovData {
                     });
                 }
             }
-            let mut intermediate_counts: HashMap<(char, char), (Vec<char>, Vec<u32>)> =
-                HashMap::new();
+            let mut intermediate_counts: BTreeMap<(char, char), (Vec<char>, Vec<u32>)> =
+                BTreeMap::new();
             for (&(k, character), &amount) in &counts {
                 intermediate_counts.insert(k, {
                     let mut vectors = intermediate_counts
@@ -171,6 +171,10 @@ impl MarkovData {
         }
     }
 
+    /// # Panics
+    ///
+    /// If the chain has a character that can't be packed into a byte
+    #[must_use]
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         for &(char1, char2) in &self.starts {
@@ -197,6 +201,7 @@ impl MarkovData {
         bytes
     }
 
+    #[must_use]
     pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
         let mut bytes_iter = bytes.iter();
         let mut starts = Vec::new();
@@ -213,7 +218,8 @@ impl MarkovData {
                 )),
             }
         }
-        let mut intermediate_counts: HashMap<(char, char), (Vec<char>, Vec<u32>)> = HashMap::new();
+        let mut intermediate_counts: BTreeMap<(char, char), (Vec<char>, Vec<u32>)> =
+            BTreeMap::new();
         // over each pair of characters that can end the word
         loop {
             let char_pair = match bytes_iter.next() {
@@ -1,7 +1,32 @@
-use crate::{Terrain, World, sim::{Snapshot, usize_to_vec}};
+use std::collections::{BTreeMap, HashMap};
 
-fn range2d(range: [usize; 4]) -> impl Iterator<Item = (usize, usize)> {
-    (range[0]..range[2]).flat_map(move |x| (range[1]..range[3]).map(move |y: usize| (x, y)))
+use crate::{
+    sim::{City, Coord, Grid, Inventory, RouteChange, Snapshot, TileShape, Water},
+    World,
+};
+
+/// The tiles in `range`, with their position relative to its top left corner. The range can
+/// run past the edges of a wrapped map.
+fn range2d(grid: Grid, range: [isize; 4]) -> impl Iterator<Item = (Coord, usize)> {
+    (range[0]..range[2]).flat_map(move |x| {
+        (range[1]..range[3]).filter_map(move |y| {
+            let tile = grid.step(Coord::new(0, 0), x, y)?;
+            Some((
+                Coord::new((x - range[0]) as usize, (y - range[1]) as usize),
+                tile,
+            ))
+        })
+    })
+}
+
+/// Where a tile's column starts on the map, in unscaled pixels. Odd rows of hexes sit half a
+/// tile to the east.
+fn tile_left(grid: Grid, x: isize, y: isize) -> isize {
+    if grid.shape() == TileShape::Hex && y.rem_euclid(2) == 1 {
+        x * 20 + 10
+    } else {
+        x * 20
+    }
 }
 
 const REPORT_STYLE: &str = "
@@ -65,86 +90,147 @@ const REPORT_STYLE: &str = "
                     rgba(255,255,255,0) 100%
                 );
             }
+            .hex {
+                clip-path: polygon(50% 0%, 100% 25%, 100% 75%, 50% 100%, 0% 75%, 0% 25%);
+            }
             .small_chart {
                 display: inline-block;
                 width: 30%;
             }";
 
-fn get_trade_connections(world: &World, range: [usize; 4], scale: usize) -> String {
+/// A line from `start` to `start + (dx, dy)`, both in unscaled pixels
+fn trade_line(start: (isize, isize), (dx, dy): (isize, isize), scale: usize) -> String {
+    let class_name = {
+        if dx == 0 {
+            "tradevertical"
+        } else if dy == 0 {
+            "tradehorizontal"
+        } else if dx * dy > 0 {
+            "traderight"
+        } else {
+            "tradeleft"
+        }
+    };
+    let scale = scale as isize;
+    let left = (start.0 + dx.min(0) + 15) / scale;
+    let top = (start.1 + dy.min(0) + 15) / scale;
+    let w = dx.abs() / scale + 4;
+    let h = dy.abs() / scale + 4;
+    format!(
+        "<span class=\"{class_name}\"
+                style=\"
+                    position:absolute;
+                    left: {left}px;
+                    top: {top}px;
+                    width: {w}px;
+                    height: {h}px;
+                \"></span>"
+    )
+}
+
+fn get_trade_connections(world: &World, scale: usize) -> String {
+    let grid = world.config.grid();
     world
         .trade_connections_list
         .iter()
-        .map(|(first, second)| {
-            let first = usize_to_vec(*first, &world.config);
-            let second = usize_to_vec(*second, &world.config);
-            let dy = second[1] as i32 - first[1] as i32;
-            let dx = second[0] as i32 - first[0] as i32;
-            let class_name = {
-                if dx == 0 {
-                    "tradevertical"
-                } else if dy == 0 {
-                    "tradehorizontal"
-                } else if dx * dy > 0 {
-                    "traderight"
-                } else {
-                    "tradeleft"
-                }
+        .filter_map(|route| world.trade_connections.get(route))
+        .flat_map(|route| route.path.windows(2))
+        .map(|step| {
+            let (first, second) = (step[0], step[1]);
+            let offset = grid.offset(first, second);
+            // each step of a route is a line from one tile to where the next one is, `offset`
+            // away
+            let half = |from: usize, (dx, dy): (isize, isize)| {
+                let Coord { x, y } = grid.coord(from);
+                let (x, y) = (x as isize, y as isize);
+                let left = tile_left(grid, x, y);
+                trade_line(
+                    (left, y * 20),
+                    (tile_left(grid, x + dx, y + dy) - left, dy * 20),
+                    scale,
+                )
             };
-            let left = ((first[0].min(second[0]) - range[0]) * 20 + 15) / scale;
-            let top = ((first[1].min(second[1]) - range[1]) * 20 + 15) / scale;
-            let w = (dx.abs() * 20) / scale as i32 + 4;
-            let h = (dy.abs() * 20) / scale as i32 + 4;
-            format!(
-                "<span class=\"{class_name}\"
-                        style=\"
-                            position:absolute;
-                            left: {left}px;
-                            top: {top}px;
-                            width: {w}px;
-                            height: {h}px;
-                        \"></span>"
-            )
+            let start = grid.coord(first);
+            let line = half(first, offset);
+            // a step across a wrapped edge is drawn as two halves, one from each tile
+            if grid.index(Coord::new(
+                (start.x as isize + offset.0) as usize,
+                (start.y as isize + offset.1) as usize,
+            )) == Some(second)
+            {
+                line
+            } else {
+                line + &half(second, (-offset.0, -offset.1))
+            }
         })
         .collect()
 }
 
-fn map(world: &World, range: [usize; 4], scale: usize, include_trade: bool) -> String {
+fn map(world: &World, range: [isize; 4], scale: usize, include_trade: bool) -> String {
+    let grid = world.config.grid();
     let s = 20 / scale;
-    let width = (range[2] - range[0]) / scale;
-    let height = (range[3] - range[1]) / scale;
-    let squares: String = range2d(range)
-        .map(|(x, y)| {
-            let idx = x + y * world.config.world_size.0;
-            let color = match world.region_list[world.region_map[idx]].terrain() {
-                Terrain::Ocean => "#008",
-                Terrain::Plain => "#080",
-                Terrain::Forest => "#084",
-                Terrain::Mountain => "#444",
-                Terrain::Desert => "#880",
-                Terrain::Jungle => "#480",
+    let width = (range[2] - range[0]) as usize / scale;
+    let height = (range[3] - range[1]) as usize / scale;
+    // hexes are taller than they are wide, and overlap the rows above and below by a quarter
+    let (class, tile_height, overlap, shift) = match grid.shape() {
+        TileShape::Square => ("", s, 0, 0),
+        TileShape::Hex => ("hex", 26 / scale, 3, 10 / scale),
+    };
+    // where a tile on screen goes, leaving the hex shift to the row it's really on
+    let left = |screen: Coord, tile: usize| {
+        tile_left(grid, screen.x as isize, grid.coord(tile).y as isize) as usize
+    };
+    let squares: String = range2d(grid, range)
+        .map(|(screen, idx)| {
+            let terrain = world.region_list[world.region_map[idx]].terrain();
+            let color = match world.waters.get(idx) {
+                Some(Water::Lake) => {
+                    let [r, g, b] = Water::Lake.color();
+                    format!("rgb({r}, {g}, {b})")
+                }
+                _ => world.biomes[terrain].css_color(),
+            };
+            let (left, top) = (
+                (left(screen, idx) + 3) / scale + 2,
+                (screen.y * 20 + 3 - overlap) / scale + 2,
+            );
+            // a river is a dot in the middle of each of its tiles
+            let river = if world.waters.get(idx) == Some(Water::River) {
+                let [r, g, b] = Water::River.color();
+                format!(
+                    "<span style=\"width:{w}px;
+                        height:{w}px;
+                        border-radius:50%;
+                        background-color: rgb({r}, {g}, {b});
+                        position:absolute;
+                        left:{left}px;
+                        top:{top}px;\"></span>",
+                    w = s / 2,
+                    left = left + s / 4,
+                    top = top + tile_height / 2 - s / 4,
+                )
+            } else {
+                String::new()
             };
             format!(
-                "<span style=\"width:{s}px;
-                    height:{s}px;
+                "<span class=\"{class}\" style=\"width:{s}px;
+                    height:{tile_height}px;
                     background-color: {color};
                     position:absolute;
                     left:{left}px;
-                    top:{top}px;\"></span>",
-                left = ((x - range[0]) * 20 + 3) / scale + 2,
-                top = ((y - range[1]) * 20 + 3) / scale + 2,
+                    top:{top}px;\"></span>{river}"
             )
         })
         .collect();
     let trade_routes = if include_trade {
-        get_trade_connections(world, range, scale)
+        get_trade_connections(world, scale)
     } else {
         String::new()
     };
-    let cities: String = range2d(range)
-        .map(|(x, y)| x + y * world.config.world_size.0)
-        .filter_map(|idx| world.city_list.get(&idx))
-        .map(|city| {
-            let [x, y] = usize_to_vec(city.pos(), &world.config)[..] else {return String::new()};
+    let cities: String = range2d(grid, range)
+        .filter_map(|(screen, tile)| Some((screen, world.city_list.get(&tile)?)))
+        .map(|(screen, city)| {
+            let Coord { x, y } = grid.coord(city.pos());
             format!(
                 "<a href=\"#city_({x}, {y})\" class=\"tooltip\" style=\"
                 border-radius: {border_radius}px;
@@ -160,8 +246,8 @@ fn map(world: &World, range: [usize; 4], scale: usize, include_trade: bool) -> S
                 border_radius = 10 / scale,
                 size = 8 / scale,
                 border_width = 4 / scale,
-                left = ((x - range[0]) * 20 + 5) / scale + 2,
-                top = ((y - range[1]) * 20 + 5) / scale + 2,
+                left = (left(screen, city.pos()) + 5) / scale + 2,
+                top = (screen.y * 20 + 5) / scale + 2,
                 name = city.name(),
             )
         })
@@ -171,57 +257,268 @@ fn map(world: &World, range: [usize; 4], scale: usize, include_trade: bool) -> S
             width:{w}px;
             height:{h}px;
             position:relative;
+            overflow:hidden;
             background-color:black;
             padding:5px\">
             {squares}
             {trade_routes}
             {cities}
         </div>",
-        w = width * 20,
+        // leave room for the shifted rows of hexes
+        w = width * 20 + shift,
         h = height * 20,
     )
 }
 
+#[must_use]
 pub fn chart_script(world: &World) -> String {
+    // each city's balance of trade, year by year
+    let mut balances: HashMap<usize, BTreeMap<u32, f32>> = HashMap::new();
+    for trade in world.trade_ledger() {
+        *balances
+            .entry(trade.seller())
+            .or_default()
+            .entry(trade.year)
+            .or_default() += trade.value();
+        *balances
+            .entry(trade.buyer)
+            .or_default()
+            .entry(trade.year)
+            .or_default() -= trade.value();
+    }
     let draw_chart: String = world
         .city_list
         .iter()
         .map(|(pos, city)| 
         {
             let snapshots: Vec<(String, &Snapshot)> = (0..).map_while(|n| {
-                let idx: String = (n*100).to_string();
+                let idx: String = (n * world.config.snapshot_interval).to_string();
                 city.data().get(&idx).map(|snapshot| (idx, snapshot))}).collect();
             let pop_data: String = snapshots.iter().map(|(year, snapshot)| 
                 format!(",['{year}', {population}]", population = snapshot.population)
             ).collect();
-            let item_keys = &city.data().get("100").unwrap().imports;
             let import_data: String = snapshots.iter().map(|(year, snapshot)|
         {
             String::new()
         }
             ).collect();
-            let [x, y] = usize_to_vec(*pos, &world.config)[..] else { return String::new() };
+            let treasury_data: String = snapshots.iter().map(|(year, snapshot)|
+                format!(",['{year}', {treasury}]", treasury = snapshot.treasury)
+            ).collect();
+            let mut running = 0.0;
+            let balance_data: String = balances.get(pos).into_iter().flatten().map(|(year, balance)| {
+                running += balance;
+                format!(",[{year}, {running}]")
+            }).collect();
+            let Coord { x, y } = world.config.grid().coord(*pos);
             format!(
-            "const pop_data = google.visualization.arrayToDataTable([['Year', 'Population']{pop_data}]);
+            "{{
+            const pop_data = google.visualization.arrayToDataTable([['Year', 'Population']{pop_data}]);
             const pop_chart = new google.visualization.LineChart(document.getElementById('popchart_({x}, {y})'));
-            pop_chart.draw(pop_data, pop_options);"
+            pop_chart.draw(pop_data, pop_options);
+            const treasury_data = google.visualization.arrayToDataTable([['Year', 'Treasury']{treasury_data}]);
+            const treasury_chart = new google.visualization.LineChart(document.getElementById('treasurychart_({x}, {y})'));
+            treasury_chart.draw(treasury_data, treasury_options);
+            const balance_data = google.visualization.arrayToDataTable([['Year', 'Balance']{balance_data}]);
+            const balance_chart = new google.visualization.LineChart(document.getElementById('balancechart_({x}, {y})'));
+            balance_chart.draw(balance_data, balance_options);
+            }}"
             )
         })
         .collect();
+    // count back from the routes open now to how many there were at the start
+    let mut routes = world.trade_connections.len() as isize;
+    for event in &world.trade_history {
+        routes += if event.change.opens() { -1 } else { 1 };
+    }
+    let route_data = format!(",[0, {routes}]")
+        + &world
+            .trade_history
+            .iter()
+            .map(|event| {
+                routes += if event.change.opens() { 1 } else { -1 };
+                format!(",[{year}, {routes}]", year = event.year)
+            })
+            .collect::<String>();
     format!(
         "google.charts.load('current', {{'packages':['corechart']}});
     google.charts.setOnLoadCallback(drawChart);
     function drawChart() {{
         const pop_options = {{'title':'City Population'}};
+        const treasury_options = {{'title':'City Treasury'}};
+        const balance_options = {{'title':'Balance of Trade, All Time'}};
         {draw_chart}
+        const route_data = google.visualization.arrayToDataTable([['Year', 'Routes']{route_data}]);
+        const route_chart = new google.visualization.LineChart(document.getElementById('routechart'));
+        route_chart.draw(route_data, {{'title':'Open Trade Routes'}});
     }}"
     )
 }
 
+/// The name of the city at `pos`, or where it was if it's gone
+fn city_name(world: &World, pos: usize) -> String {
+    world.city(pos).map_or_else(
+        || {
+            let Coord { x, y } = world.config.grid().coord(pos);
+            format!("({x}, {y})")
+        },
+        |city| city.name().clone(),
+    )
+}
+
+/// The `n` largest `amounts`, largest first, as "name (amount)"
+fn top(amounts: impl Iterator<Item = (String, f32)>, n: usize) -> String {
+    let mut amounts: Vec<_> = amounts.filter(|&(_, amount)| amount > 0.0).collect();
+    if amounts.is_empty() {
+        return String::from("none");
+    }
+    amounts.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    amounts
+        .iter()
+        .take(n)
+        .map(|(name, amount)| format!("{name} ({amount:.0})"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The goods in `inventory`, by name
+fn goods<'a>(
+    world: &'a World,
+    inventory: &'a Inventory,
+) -> impl Iterator<Item = (String, f32)> + 'a {
+    let items = world.items();
+    inventory
+        .iter()
+        .map(|(item, amount)| (items.item(item).to_string(items), amount))
+}
+
+/// Who the city trades with the most, in coins, and what it buys and sells the most of
+fn city_trade(world: &World, city: &City) -> String {
+    let mut imports = Inventory::default(world.items());
+    let mut exports = Inventory::default(world.items());
+    for totals in city.partners().values() {
+        for (item, amount) in totals.bought.iter() {
+            imports.add(item, amount);
+        }
+        for (item, amount) in totals.sold.iter() {
+            exports.add(item, amount);
+        }
+    }
+    let partners = city
+        .partners()
+        .iter()
+        .map(|(&partner, totals)| (city_name(world, partner), totals.value()));
+    format!(
+        "<p>Top partners: {partners}<br>Top imports: {imports}<br>Top exports: {exports}</p>",
+        partners = top(partners, 3),
+        imports = top(goods(world, &imports), 3),
+        exports = top(goods(world, &exports), 3),
+    )
+}
+
+/// The pairs of cities that have traded the most coins' worth, and what each buys from the
+/// other
+fn partnerships(world: &World) -> String {
+    let mut pairs: Vec<_> = world
+        .city_list
+        .iter()
+        .flat_map(|(&pos, city)| {
+            city.partners()
+                .range(pos + 1..)
+                .map(move |(&partner, totals)| (pos, partner, totals))
+        })
+        .collect();
+    pairs.sort_by(|(_, _, a), (_, _, b)| b.value().total_cmp(&a.value()));
+    let rows: String = pairs
+        .iter()
+        .take(20)
+        .map(|&(pos, partner, totals)| {
+            format!(
+                "<tr><td>{first}</td><td>{second}</td><td>{value:.0}</td><td>{bought}</td>\
+                <td>{sold}</td></tr>",
+                first = city_name(world, pos),
+                second = city_name(world, partner),
+                value = totals.value(),
+                bought = top(goods(world, &totals.bought), 3),
+                sold = top(goods(world, &totals.sold), 3),
+            )
+        })
+        .collect();
+    format!(
+        "<table><tr><th>City</th><th>Partner</th><th>Coins Traded</th>\
+        <th>City Buys</th><th>Partner Buys</th></tr>{rows}</table>"
+    )
+}
+
+/// Every opening and closing of a trade route, newest first
+fn trade_changes(world: &World) -> String {
+    let name = |pos| city_name(world, pos);
+    world
+        .trade_history
+        .iter()
+        .rev()
+        .map(|event| {
+            let what = match event.change {
+                RouteChange::Isolation => "began trading, one of them having had no partners",
+                RouteChange::Prosperity => "began trading as one of them grew prosperous",
+                RouteChange::Discovery => "began trading after a traveler found the way",
+                RouteChange::Disuse => "stopped trading for lack of business",
+                RouteChange::Danger => "stopped trading for fear of monsters",
+                RouteChange::Ruin => "stopped trading as one of them died out",
+            };
+            format!(
+                "<li>{year}: {first} and {second} {what}</li>",
+                year = event.year,
+                first = name(event.route.0),
+                second = name(event.route.1),
+            )
+        })
+        .collect()
+}
+
+/// Living cities from richest to poorest, with what they've earned and spent trading over
+/// their whole history
+fn wealth_table(world: &World) -> String {
+    let mut cities: Vec<_> = world
+        .city_list
+        .values()
+        .filter(|city| city.population() > 0)
+        .collect();
+    cities.sort_by(|a, b| b.treasury().total_cmp(&a.treasury()));
+    let rows: String = cities
+        .iter()
+        .map(|city| {
+            let (earned, spent) = city.data().values().fold(
+                (city.earned(), city.spent()),
+                |(earned, spent), snapshot| (earned + snapshot.earned, spent + snapshot.spent),
+            );
+            let treasury = city.treasury();
+            format!(
+                "<tr><td>{name}</td><td>{population}</td><td>{treasury}</td><td>{earned:.0}</td>\
+                <td>{spent:.0}</td><td>{balance:+.0}</td></tr>",
+                name = city.name(),
+                population = city.population(),
+                treasury = if treasury < 0.0 {
+                    format!("{:.0} in debt", -treasury)
+                } else {
+                    format!("{treasury:.0}")
+                },
+                balance = earned - spent,
+            )
+        })
+        .collect();
+    format!(
+        "<table><tr><th>City</th><th>Population</th><th>Treasury</th><th>Earned</th>\
+        <th>Spent</th><th>Balance of Trade</th></tr>{rows}</table>"
+    )
+}
+
+#[must_use]
 pub fn report(world: &World) -> String {
+    let grid = world.config.grid();
     let mainmap = map(
         world,
-        [0, 0, world.config.world_size.0, world.config.world_size.1],
+        [0, 0, grid.width() as isize, grid.height() as isize],
         1,
         true,
     );
@@ -230,28 +527,40 @@ pub fn report(world: &World) -> String {
         .city_list
         .iter()
         .map(|(pos, city)| {
-            let [x, y] = usize_to_vec(*pos, &world.config)[..] else {return String::new()};
+            let Coord { x, y } = world.config.grid().coord(*pos);
             format!(
                 "<h3 id=\"city_({x}, {y})\">{name}</h3>
                     {map}
                     <div class=\"small_chart\" id=\"popchart_({x}, {y})\"></div>
+                    <div class=\"small_chart\" id=\"treasurychart_({x}, {y})\"></div>
+                    <div class=\"small_chart\" id=\"balancechart_({x}, {y})\"></div>
+                    {trade}
                     <div class=\"small_chart\" id=\"importchart_({x}, {y})\"></div>
                     <div class=\"small_chart\" id=\"prodchart_({x}, {y})\"></div>",
                 name = city.name(),
-                map = map(
-                    world,
-                    [
-                        x.max(5) - 5,
-                        y.max(5) - 5,
-                        x.min(world.config.world_size.0 - 6) + 6,
-                        y.min(world.config.world_size.1 - 6) + 6
-                    ],
-                    2,
-                    false
-                )
+                trade = city_trade(world, city),
+                map = {
+                    // clamp the map to the edges that don't wrap
+                    let (x, y) = (x as isize, y as isize);
+                    let (width, height) = (grid.width() as isize, grid.height() as isize);
+                    let (left, right) = if grid.topology().wraps_x() {
+                        (x - 5, x + 6)
+                    } else {
+                        ((x - 5).max(0), (x + 6).min(width))
+                    };
+                    let (top, bottom) = if grid.topology().wraps_y() {
+                        (y - 5, y + 6)
+                    } else {
+                        ((y - 5).max(0), (y + 6).min(height))
+                    };
+                    map(world, [left, top, right, bottom], 2, false)
+                }
             )
         })
         .collect();
+    let trade = trade_changes(world);
+    let wealth = wealth_table(world);
+    let partnerships = partnerships(world);
     let resources = String::new();
     let regions = String::new();
     format!(
@@ -272,6 +581,8 @@ pub fn report(world: &World) -> String {
         <ul>
             <li><a href=\"#h2_Magic\">Magic</a></li>
             <li><a href=\"#h2_Cities\">Cities</a></li>
+            <li><a href=\"#h2_Trade\">Trade</a></li>
+            <li><a href=\"#h2_Wealth\">Wealth</a></li>
             <li><a href=\"#h2_Resources\">Resources</a></li>
             <li><a href=\"#h2_Regions\">Regions</a></li>
         </ul>
@@ -280,6 +591,14 @@ pub fn report(world: &World) -> String {
         {magic}
         <h2 id=\"h2_Cities\">Cities</h2>
         {cities}
+        <h2 id=\"h2_Trade\">Trade</h2>
+        <div class=\"small_chart\" id=\"routechart\"></div>
+        <h3>Who Trades What With Whom</h3>
+        {partnerships}
+        <h3>Routes Opened and Closed</h3>
+        <ul>{trade}</ul>
+        <h2 id=\"h2_Wealth\">Wealth</h2>
+        {wealth}
         <h2 id=\"h2_Resources\">Resources</h2>
         {resources}
         <h2 id=\"h2_Regions\">Regions</h2>
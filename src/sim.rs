@@ -1,121 +1,273 @@
 mod city;
+mod grid;
 mod item;
 mod logging;
+mod trade;
+mod water;
 mod world;
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::BuildHasher,
+};
 
 pub use city::City;
-pub use item::{Inventory, Item, ItemType};
-pub use logging::{HistoricalEvent, Snapshot};
-pub use world::{Monster, Region, Species, Terrain};
+pub use grid::{Coord, Grid, TileShape, Topology};
+pub use item::{Inventory, Item, ItemIndex, ItemType, PriceOverrides, Pricing, Want};
+pub use logging::{notify, HistoricalEvent, Observer, Snapshot, TickEvent};
+pub use trade::{
+    find_routes, forget_trades_before, routes_from, RouteChange, RouteEvent, RouteKind, Trade,
+    TradeRoute, TradeTotals,
+};
+pub use water::{Water, Waters};
+pub use world::{Biome, Biomes, Border, Climate, Region, Species};
 
 use crate::{Config, Items};
 
-pub fn handle_trade(
+/// Clear a year's basket of goods along `route`.
+///
+/// Each city buys what it gains the most on from the other, paying the seller's price, until the
+/// route is full or nothing more is worth carrying. Gives every good bought, for the ledger.
+pub fn handle_trade<S: BuildHasher>(
     route: (usize, usize),
-    city_list: &mut HashMap<usize, City>,
-    trade_connections: &mut HashMap<(usize, usize), i32>,
+    city_list: &mut BTreeMap<usize, City>,
+    trade_connections: &mut HashMap<(usize, usize), TradeRoute, S>,
+    current_year: u32,
     config: &Config,
     items: &Items,
-) -> Option<()> {
-    // immutable references to generate the resource lists
-    let first_city = city_list.get(&route.0)?;
-    let second_city = city_list.get(&route.1)?;
+    observers: &[Box<dyn Observer>],
+) -> Vec<Trade> {
+    let mut trades = Vec::new();
+    let (Some(trade_route), Some(first), Some(second)) = (
+        trade_connections.get_mut(&route),
+        city_list.get(&route.0),
+        city_list.get(&route.1),
+    ) else {
+        return trades;
+    };
+    let mut capacity = trade_route.capacity(config.trade_volume, config.trade_reach);
+    for (buyer, seller, item) in basket(route, first, second, items) {
+        let price = city_list[&seller].economy().get(item);
+        let amount = purchase(
+            &city_list[&buyer],
+            &city_list[&seller],
+            item,
+            capacity,
+            config,
+        );
+        if amount <= 0.0 {
+            continue;
+        }
+        if let Some(city) = city_list.get_mut(&seller) {
+            city.sell(buyer, item, amount, price, items);
+        }
+        if let Some(city) = city_list.get_mut(&buyer) {
+            city.buy(seller, item, amount, price, items);
+        }
+        capacity -= amount;
+        trades.push(Trade {
+            year: current_year,
+            route,
+            buyer,
+            item,
+            amount,
+            price,
+        });
+        notify(
+            observers,
+            current_year,
+            &TickEvent::Trade {
+                route,
+                buyer,
+                item,
+                amount,
+                price,
+            },
+        );
+        if capacity < 1.0 {
+            break;
+        }
+    }
+    if !trades.is_empty() {
+        trade_route.record_trade(
+            config.route_growth,
+            trades.iter().map(|trade| trade.amount).sum(),
+            trades.iter().map(Trade::value).sum(),
+        );
+    }
+    trades
+}
 
-    let (first_city_supply, second_city_supply): (Vec<f32>, Vec<f32>) = {
-        (0..items.all.len())
-            .map(|item| {
-                (
-                    second_city.economy().get(item) * config.trade_volume
-                        / first_city.economy().get(item),
-                    first_city.economy().get(item) * config.trade_volume
-                        / second_city.economy().get(item),
-                )
+/// Every good worth carrying along `route`, one way or the other, as (buyer, seller, item),
+/// the widest price spread first
+fn basket(
+    route: (usize, usize),
+    first: &City,
+    second: &City,
+    items: &Items,
+) -> Vec<(usize, usize, ItemIndex)> {
+    let directions = [
+        (route.0, route.1, first, second),
+        (route.1, route.0, second, first),
+    ];
+    let mut goods: Vec<_> = directions
+        .into_iter()
+        .flat_map(|(buyer_pos, seller_pos, buyer, seller)| {
+            items.iter().filter_map(move |(item, _)| {
+                let spread = buyer.economy().get(item) - seller.economy().get(item);
+                (spread > 0.0 && seller.economy().get(item) > 0.0)
+                    .then_some((spread, (buyer_pos, seller_pos, item)))
             })
-            .unzip()
-    };
+        })
+        .collect();
+    goods.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    goods.into_iter().map(|(_, good)| good).collect()
+}
 
-    let first_resource = {
-        let tup = first_city_supply
-            .iter()
-            .enumerate()
-            .min_by_key(|(_, &amount)| amount as i64)?;
-        (tup.0, tup.1.floor())
-    };
-    let second_resource = {
-        let tup = second_city_supply
-            .iter()
-            .enumerate()
-            .min_by_key(|(_, &amount)| amount as i64)?;
-        (tup.0, tup.1.floor())
-    };
+/// How much of `item` `buyer` buys from `seller` with `capacity` left on the route: as much as
+/// it can pay for on credit, and the seller will part with at most half its stock in a year
+fn purchase(buyer: &City, seller: &City, item: ItemIndex, capacity: f32, config: &Config) -> f32 {
+    let price = seller.economy().get(item);
+    let budget = (buyer.population() as f32).mul_add(config.credit, buyer.treasury());
+    capacity
+        .min(seller.resources().get(item) / 2.0)
+        .min(budget / price)
+        .floor()
+}
 
-    if first_resource.1.is_nan()
-        || second_resource.1.is_nan()
-        || first_resource.1 <= 0.0
-        || second_resource.1 <= 0.0
-    {
-        return None;
-    }
+#[must_use]
+pub fn inverse_add(a: f32, b: f32) -> f32 {
+    (a * b) / (a + b)
+}
 
-    // mutable references to update the cities' contents.
-    // They have to be like this because you can't have two mutable references at the same time
-    let first_city = city_list.get_mut(&route.0)?;
-    first_city.import_resource(first_resource.0, first_resource.1);
-    first_city.import_resource(second_resource.0, -second_resource.1);
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    };
 
-    let second_city = city_list.get_mut(&route.1)?;
-    second_city.import_resource(first_resource.0, -first_resource.1);
-    second_city.import_resource(second_resource.0, second_resource.1);
+    use super::{
+        basket, handle_trade, logging::tests::Recorder, purchase, City, Inventory, Item, ItemType,
+        Observer, PriceOverrides, RouteKind, TickEvent, TradeRoute,
+    };
+    use crate::{Config, Items, Jsonizable};
 
-    trade_connections.insert(route, *trade_connections.get(&route).unwrap_or(&0) + 1);
-    None
-}
+    pub(super) fn plants(names: &[&str]) -> Items {
+        let plants = names
+            .iter()
+            .map(|&name| ItemType {
+                name: String::from(name),
+                rarity: 1,
+                abundance: 1,
+                value: 1,
+                taming: 0,
+                overrides: PriceOverrides::default(),
+            })
+            .collect();
+        Items::from_item_types(plants, Vec::new(), Vec::new(), Vec::new())
+    }
 
-pub fn get_adj(center: usize, radius: usize, config: &Config) -> Vec<usize> {
-    if radius == 0 {
-        vec![
-            center + 1,
-            center - 1,
-            center + config.world_size.0,
-            center - config.world_size.0,
-        ]
-    } else {
-        let mut adj: Vec<usize> = Vec::new();
-        for x in 0..=(2 * radius) {
-            for y in 0..=(2 * radius) {
-                if (x, y) == (radius, radius) {
-                    continue;
-                }
-                let positive = center + x + y * config.world_size.0;
-                let negative = radius * (1 + config.world_size.0);
-                if negative > positive || (center / config.world_size.0) + y < radius {
-                    continue;
-                }
-                let adj_index: usize = positive - negative;
-                if adj_index < config.world_size.0 * config.world_size.1
-                    && (adj_index / config.world_size.0
-                        == (center / config.world_size.0) + y - radius)
-                {
-                    adj.push(adj_index);
-                }
-            }
+    /// A city of a hundred at `pos`, with the given prices and stock of each plant
+    pub(super) fn city(pos: usize, prices: &[f32], stock: &[f32], items: &Items) -> City {
+        let config = Config::default();
+        let name = String::from("Test");
+        let new = City::new(pos, name, Inventory::default(items), HashMap::new(), items);
+        let mut json = new.jsonize(&config, items);
+        for (plant, &price) in prices.iter().enumerate() {
+            json["economy"][Item::Plant(plant as u8).to_string(items)] = price.into();
+        }
+        for (plant, &amount) in stock.iter().enumerate() {
+            json["resources"][Item::Plant(plant as u8).to_string(items)] = amount.into();
         }
-        adj
+        City::dejsonize(&json, &config, items).unwrap()
     }
-}
 
-pub fn distance(a: usize, b: usize, config: &Config) -> f32 {
-    ((((a / config.world_size.0) as i32 - (b / config.world_size.0) as i32).pow(2)
-        + ((a % config.world_size.0) as i32 - (b % config.world_size.0) as i32).pow(2)) as f32)
-        .sqrt()
-}
+    #[test]
+    fn basket_goes_widest_spread_first() {
+        let items = plants(&["Wheat", "Rice", "Barley"]);
+        let [wheat, rice] = [0, 1].map(|plant| items.index(Item::Plant(plant)).unwrap());
+        let first = city(0, &[5.0, 2.0, 3.0], &[], &items);
+        // nobody sells barley to the first city, since the second puts no price on it
+        let second = city(1, &[1.0, 4.0, 0.0], &[], &items);
+        assert_eq!(
+            basket((0, 1), &first, &second, &items),
+            vec![(0, 1, wheat), (1, 0, rice)]
+        );
+    }
 
-pub fn inverse_add(a: f32, b: f32) -> f32 {
-    (a * b) / (a + b)
-}
+    #[test]
+    fn purchase_stops_at_capacity_stock_or_credit() {
+        let items = plants(&["Wheat"]);
+        let wheat = items.index(Item::Plant(0)).unwrap();
+        let config = Config::default();
+        // a hundred people with a coin each can spend two hundred coins on credit
+        let buyer = city(0, &[], &[], &items);
+        let bought = |price, stock, capacity| {
+            let seller = city(1, &[price], &[stock], &items);
+            purchase(&buyer, &seller, wheat, capacity, &config)
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        assert!(close(bought(4.0, 1000.0, 10.0), 10.0));
+        assert!(close(bought(4.0, 50.0, 100.0), 25.0));
+        assert!(close(bought(4.0, 1000.0, 100.0), 50.0));
+        assert!(close(bought(3.0, 1000.0, 100.0), 66.0));
+    }
 
-pub fn usize_to_vec(index: usize, config: &Config) -> Vec<usize> {
-    vec![index % config.world_size.0, index / config.world_size.0]
+    #[test]
+    fn trades_fill_the_route_and_are_reported() {
+        let items = plants(&["Wheat", "Rice"]);
+        let config = Config {
+            trade_volume: 100.0,
+            ..Config::default()
+        };
+        // the first city buys up all the second's spare wheat, and fills the rest of the route
+        // with rice going the other way
+        let mut city_list = BTreeMap::from([
+            (0, city(0, &[5.0, 2.0], &[0.0, 1000.0], &items)),
+            (1, city(1, &[1.0, 4.0], &[60.0, 0.0], &items)),
+        ]);
+        let route = TradeRoute {
+            kind: RouteKind::Land,
+            path: vec![0, 1],
+            cost: 0.0,
+            trades: 0,
+            strength: 0.5,
+            carried: 0.0,
+            value: 0.0,
+        };
+        assert!((route.capacity(config.trade_volume, config.trade_reach) - 100.0).abs() < 1e-4);
+        let mut trade_connections = HashMap::from([((0, 1), route)]);
+        let recorder = Arc::new(Recorder::default());
+        let observers: Vec<Box<dyn Observer>> = vec![Box::new(recorder.clone())];
+        let trades = handle_trade(
+            (0, 1),
+            &mut city_list,
+            &mut trade_connections,
+            3,
+            &config,
+            &items,
+            &observers,
+        );
+        let bought: Vec<_> = trades
+            .iter()
+            .map(|trade| (trade.buyer, trade.amount))
+            .collect();
+        assert_eq!(bought, vec![(0, 30.0), (1, 70.0)]);
+        let reported: Vec<_> = trades
+            .iter()
+            .map(|trade| {
+                let event = TickEvent::Trade {
+                    route: trade.route,
+                    buyer: trade.buyer,
+                    item: trade.item,
+                    amount: trade.amount,
+                    price: trade.price,
+                };
+                (3, format!("{event:?}"))
+            })
+            .collect();
+        assert_eq!(recorder.take(), reported);
+        assert_eq!(trade_connections[&(0, 1)].trades, 1);
+    }
 }
@@ -1,21 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use json::{array, object, JsonValue};
-use rand::{
-    distributions::WeightedIndex, prelude::Distribution, rngs::ThreadRng, seq::SliceRandom, Rng,
-};
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
 use strum::IntoEnumIterator;
 
 use crate::{
-    jsonize::{json_array_to_usize, json_int, json_string, Jsonizable},
+    jsonize::{json_float, json_int, json_string, Jsonizable},
     magic::MagicSystem,
     mkv::MarkovData,
     mut_loop,
-    sim::{distance, get_adj, inverse_add, usize_to_vec},
+    sim::inverse_add,
     Config, Items, Npc, Skill,
 };
 
-use super::{HistoricalEvent, Inventory, Item, Snapshot};
+use super::{
+    notify, HistoricalEvent, Inventory, Item, ItemIndex, Observer, Snapshot, TickEvent,
+    TradeTotals, Want,
+};
 
 #[derive(Debug, Clone)]
 pub struct City {
@@ -30,25 +31,45 @@ pub struct City {
     data: HashMap<String, Snapshot>,
     production: Inventory,
     imports: Inventory,
+    exports: Inventory,
+    /// How much the city's people care for each want, around 1
+    culture: HashMap<Want, f32>,
+    /// The city's coins. Below 0, it's in debt.
+    treasury: f32,
+    /// Coins earned from exports and paid for imports since the last snapshot
+    earned: f32,
+    spent: f32,
+    /// Everything the city has ever bought from and sold to each trading partner, by its tile
+    partners: BTreeMap<usize, TradeTotals>,
 }
 
 impl Jsonizable for City {
     fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
         object! {
-            pos: usize_to_vec(self.pos, config),
+            pos: config.grid().coord(self.pos),
             name: self.name.clone(),
             population: self.population,
             homunculi: self.homunculi,
             NPCs: self.npcs.jsonize(config, items),
             data: self.data.jsonize(config, items),
             imports: self.imports.jsonize(config, items),
+            exports: self.exports.jsonize(config, items),
             production: self.production.jsonize(config, items),
             resources: self.resources.jsonize(config, items),
             economy: self.economy.jsonize(config, items),
             resource_gathering: self.resource_gathering.jsonize(config, items),
+            treasury: self.treasury,
+            earned: self.earned,
+            spent: self.spent,
             history: array![],
-            trade: array![],
+            trade: self.partners.iter().map(|(&partner, totals)| {
+                let mut totals = totals.jsonize(config, items);
+                totals["partner"] = config.grid().coord(partner).into();
+                totals
+            }).collect::<Vec<_>>(),
             artifacts: array![],
+            // cultural_values belongs to the python sim, so how much a city wants goes beside it
+            wants: self.culture.iter().map(|(want, &value)| (want.as_ref(), value)).collect::<HashMap<_, _>>(),
             cultural_values: object!{},
             library: object!{}
         }
@@ -59,7 +80,7 @@ impl Jsonizable for City {
         let JsonValue::Object(object) = src else { return None; };
         Some(Self {
             name: json_string(object.get("name")?)?,
-            pos: json_array_to_usize(object.get("pos")?, config)?,
+            pos: config.grid().dejsonize_tile(object.get("pos")?)?,
             npcs: Vec::<Npc>::dejsonize(object.get("NPCs")?, config, items)?,
             population: json_int(object.get("population")?)?,
             homunculi: json_int(object.get("homunculi")?)?,
@@ -73,33 +94,174 @@ impl Jsonizable for City {
             data: HashMap::<String, Snapshot>::dejsonize(object.get("data")?, config, items)?,
             production: Inventory::dejsonize(object.get("production")?, config, items)?,
             imports: Inventory::dejsonize(object.get("imports")?, config, items)?,
+            exports: object
+                .get("exports")
+                .and_then(|exports| Inventory::dejsonize(exports, config, items))
+                .unwrap_or_else(|| Inventory::default(items)),
+            // cities from older saves have no particular tastes
+            culture: Want::iter()
+                .map(|want| {
+                    let value = object
+                        .get("wants")
+                        .and_then(|values| json_float(&values[want.as_ref()]));
+                    (want, value.unwrap_or(1.0))
+                })
+                .collect(),
+            // cities from older saves start out with a coin a head and no trade on the books
+            treasury: object
+                .get("treasury")
+                .and_then(json_float)
+                .unwrap_or_else(|| json_int(&object["population"]).unwrap_or(0) as f32),
+            earned: object.get("earned").and_then(json_float).unwrap_or(0.0),
+            spent: object.get("spent").and_then(json_float).unwrap_or(0.0),
+            partners: match object.get("trade") {
+                Some(JsonValue::Array(trade)) => {
+                    let mut partners = BTreeMap::new();
+                    for totals in trade {
+                        partners.insert(
+                            config.grid().dejsonize_tile(&totals["partner"])?,
+                            TradeTotals::dejsonize(totals, config, items)?,
+                        );
+                    }
+                    partners
+                }
+                _ => BTreeMap::new(),
+            },
         })
     }
 }
 
 impl City {
+    #[must_use]
     pub const fn name(&self) -> &String {
         &self.name
     }
 
+    #[must_use]
     pub const fn pos(&self) -> usize {
         self.pos
     }
 
+    #[must_use]
+    pub const fn npcs(&self) -> &Vec<Npc> {
+        &self.npcs
+    }
+
+    #[must_use]
+    pub const fn population(&self) -> i32 {
+        self.population
+    }
+
+    #[must_use]
+    pub const fn homunculi(&self) -> i32 {
+        self.homunculi
+    }
+
+    #[must_use]
+    pub const fn resources(&self) -> &Inventory {
+        &self.resources
+    }
+
+    #[must_use]
     pub const fn data(&self) -> &HashMap<String, Snapshot> {
         &self.data
     }
 
+    #[must_use]
     pub const fn economy(&self) -> &Inventory {
         &self.economy
     }
 
-    pub fn import_resource(&mut self, resource: usize, amount: f32) {
+    #[must_use]
+    pub const fn culture(&self) -> &HashMap<Want, f32> {
+        &self.culture
+    }
+
+    #[must_use]
+    pub const fn treasury(&self) -> f32 {
+        self.treasury
+    }
+
+    /// Coins earned from exports since the last snapshot
+    #[must_use]
+    pub const fn earned(&self) -> f32 {
+        self.earned
+    }
+
+    /// Coins paid for imports since the last snapshot
+    #[must_use]
+    pub const fn spent(&self) -> f32 {
+        self.spent
+    }
+
+    /// Everything the city has ever bought from and sold to each trading partner, by its tile
+    #[must_use]
+    pub const fn partners(&self) -> &BTreeMap<usize, TradeTotals> {
+        &self.partners
+    }
+
+    /// What the city owns per person: its stockpile at its own prices and its treasury
+    #[must_use]
+    pub fn wealth(&self) -> f32 {
+        (self
+            .resources
+            .iter()
+            .map(|(item, amount)| amount * self.economy.get(item))
+            .sum::<f32>()
+            + self.treasury)
+            / self.population as f32
+    }
+
+    /// Import `amount` of `resource` from the city at `partner`, paying `price` for each
+    pub fn buy(
+        &mut self,
+        partner: usize,
+        resource: ItemIndex,
+        amount: f32,
+        price: f32,
+        items: &Items,
+    ) {
         self.resources.add(resource, amount);
         self.imports.add(resource, amount);
+        self.treasury -= amount * price;
+        self.spent += amount * price;
+        let totals = self
+            .partners
+            .entry(partner)
+            .or_insert_with(|| TradeTotals::new(items));
+        totals.bought.add(resource, amount);
+        totals.spent += amount * price;
     }
 
-    pub fn new(pos: usize, name: String, resource_gathering: Inventory, items: &Items) -> Self {
+    /// Export `amount` of `resource` to the city at `partner`, getting `price` for each
+    pub fn sell(
+        &mut self,
+        partner: usize,
+        resource: ItemIndex,
+        amount: f32,
+        price: f32,
+        items: &Items,
+    ) {
+        self.resources.add(resource, -amount);
+        self.exports.add(resource, amount);
+        self.treasury += amount * price;
+        self.earned += amount * price;
+        let totals = self
+            .partners
+            .entry(partner)
+            .or_insert_with(|| TradeTotals::new(items));
+        totals.sold.add(resource, amount);
+        totals.earned += amount * price;
+    }
+
+    #[must_use]
+    pub fn new(
+        pos: usize,
+        name: String,
+        resource_gathering: Inventory,
+        culture: HashMap<Want, f32>,
+        items: &Items,
+    ) -> Self {
         Self {
             pos,
             name,
@@ -109,25 +271,46 @@ impl City {
             resources: Inventory::default(items),
             economy: Inventory::default(items),
             imports: Inventory::default(items),
+            exports: Inventory::default(items),
             production: Inventory::default(items),
             data: HashMap::new(),
             resource_gathering,
+            culture,
+            // a coin a head to start trading with
+            treasury: 100.0,
+            earned: 0.0,
+            spent: 0.0,
+            partners: BTreeMap::new(),
         }
     }
 
-    fn save_snapshot(&mut self, current_year: u32, items: &Items) {
-        self.data.insert(
-            current_year.to_string(),
-            Snapshot {
-                population: self.population,
-                production: std::mem::replace(&mut self.production, Inventory::default(items)),
-                imports: std::mem::replace(&mut self.imports, Inventory::default(items)),
+    fn save_snapshot(&mut self, current_year: u32, items: &Items, observers: &[Box<dyn Observer>]) {
+        let snapshot = Snapshot {
+            population: self.population,
+            homunculi: self.homunculi,
+            living_npcs: self.npcs.iter().filter(|npc| npc.alive).count() as i32,
+            production: std::mem::replace(&mut self.production, Inventory::default(items)),
+            imports: std::mem::replace(&mut self.imports, Inventory::default(items)),
+            exports: std::mem::replace(&mut self.exports, Inventory::default(items)),
+            resources: self.resources.clone(),
+            economy: self.economy.clone(),
+            treasury: self.treasury,
+            earned: std::mem::take(&mut self.earned),
+            spent: std::mem::take(&mut self.spent),
+        };
+        notify(
+            observers,
+            current_year,
+            &TickEvent::Snapshot {
+                city: self.pos,
+                snapshot: &snapshot,
             },
         );
+        self.data.insert(current_year.to_string(), snapshot);
     }
 
     fn produce_resources(&mut self, config: &Config, items: &Items) {
-        for item in 0..items.all.len() {
+        for (item, item_kind) in items.iter() {
             let production = {
                 let production = inverse_add(
                     self.population as f32 + self.homunculi as f32,
@@ -143,152 +326,195 @@ impl City {
             self.resources.add(item, production);
             self.production.add(item, production);
             // Deplete non-renewable resources and track food resources
-            match items.all.get(item) {
-                Some(Item::Metal(_) | Item::Gem(_)) => {
-                    self.resource_gathering
-                        .add(item, -config.mineral_depletion * production);
-                }
-                _ => (),
+            if let Item::Metal(_) | Item::Gem(_) = item_kind {
+                self.resource_gathering
+                    .add(item, -config.mineral_depletion * production);
             }
         }
     }
 
-    fn demand(
-        resources: &Inventory,
-        population: f32,
-        total_food_resources: f32,
-        items: &Items,
-    ) -> Vec<f32> {
-        resources
+    /// Turn some of the city's stock of the metal coins are made of into coins
+    fn strike_coins(&mut self, config: &Config, items: &Items) {
+        let Some(metal) = items.coin_metal() else { return };
+        let struck = (self.resources.get(metal) * config.minting).floor();
+        self.resources.add(metal, -struck);
+        self.treasury += struck * items.pricing(metal).base;
+    }
+
+    /// How much of each item the city uses up this year. Food is eaten from whatever there is;
+    /// the other wants grow with the city's culture and wealth, and are spread evenly over the
+    /// items that meet them.
+    fn demand(&self, total_food_resources: f32, config: &Config, items: &Items) -> Vec<f32> {
+        let population = self.population as f32;
+        // from 0 for the destitute towards 1 for the rich
+        let affluence = {
+            let wealth = self.wealth().max(0.0);
+            wealth / (wealth + 1.0)
+        };
+        let mut kinds: HashMap<Want, usize> = HashMap::new();
+        for want in items.iter().filter_map(|(_, item)| item.want()) {
+            *kinds.entry(want).or_default() += 1;
+        }
+        self.resources
             .iter()
-            .enumerate()
-            .map(|(item, &amount)| {
-                let mut demand = 0.0;
-                if items.all.get(item).is_some_and(Item::is_food) {
-                    demand += population * amount / total_food_resources;
+            .map(|(item, amount)| {
+                let item = items.item(item);
+                if item.is_food() {
+                    return population * amount / total_food_resources;
                 }
-                demand
+                let Some(want) = item.want() else { return 0.0 };
+                let appetite = match want {
+                    Want::Luxuries => affluence * affluence,
+                    Want::Goods | Want::Animals => 0.5 + affluence,
+                };
+                population * config.consumption * self.culture.get(&want).unwrap_or(&1.0) * appetite
+                    / kinds[&want] as f32
             })
             .collect()
     }
 
-    pub fn tick(
+    #[allow(clippy::too_many_arguments)]
+    pub fn tick<R: Rng + ?Sized>(
         &mut self,
-        rng: &mut ThreadRng,
+        rng: &mut R,
         current_year: u32,
         config: &Config,
         items: &Items,
         magic: &MagicSystem,
         markov_data_npc: &MarkovData,
+        observers: &[Box<dyn Observer>],
+        passable: &[bool],
     ) {
         // Save data
-        if current_year % 100 == 0 {
-            self.save_snapshot(current_year, items);
+        if current_year.is_multiple_of(config.snapshot_interval) {
+            self.save_snapshot(current_year, items, observers);
         }
         if self.population <= 0 {
             return;
         }
         self.produce_resources(config, items);
+        self.strike_coins(config, items);
         // count food resources
         let mut total_food_resources = 0.0;
-        for i in 0..items.all.len() {
-            if matches!(items.all[i], Item::Fish | Item::Meat(_) | Item::Plant(_)) {
-                total_food_resources += self.resources.get(i);
+        for (index, item) in items.iter() {
+            if matches!(item, Item::Fish | Item::Meat(_) | Item::Plant(_)) {
+                total_food_resources += self.resources.get(index);
             }
         }
         // figure out demand for all the items
-        let demand = Self::demand(
-            &self.resources,
-            self.population as f32,
-            total_food_resources,
-            items,
-        );
-        // set the price of everything based on demand
+        let demand = self.demand(total_food_resources, config, items);
+        // set the price of everything from how much is wanted against how much there is
         self.economy = Inventory::from(
             demand
                 .iter()
-                .enumerate()
-                .map(|(item, &amount)| {
-                    let price: f32 = match items.all.get(item) {
-                        Some(Item::MetalGood(_)) => 4.0,
-                        Some(Item::CutGem(_)) => 10.0,
-                        Some(Item::TameAnimal(_)) => 5.0,
-                        Some(Item::Meat(_)) => 2.0,
-                        _ => 1.0,
-                    };
-                    let exp: f32 = amount / { (self.population as f32 - amount).exp() };
-                    let val = price * 1.1f32.powf(exp);
-                    if val.is_nan() {
+                .zip(self.resources.iter())
+                .map(|(&amount, (item, stock))| {
+                    let price = items.pricing(item).price(amount, stock);
+                    if price.is_nan() {
                         0.0
                     } else {
-                        val
+                        price
                     }
                 })
                 .collect::<Vec<_>>(),
         );
-        // make sure nothing is negative
-        for (item, amount) in self.resources.iter_mut().enumerate() {
-            *amount = (*amount - demand[item]).clamp(0.0, f32::MAX);
+        // use up what's wanted, making sure nothing is negative
+        for ((_, amount), demand) in self.resources.iter_mut().zip(&demand) {
+            *amount = (*amount - demand).clamp(0.0, f32::MAX);
         }
         let net_food = total_food_resources - self.population as f32;
 
         // At most, half of people die and 2% are born
+        let old_population = self.population;
         self.population += {
             let diff = net_food * config.population_constant;
             diff.floor() as i32 + i32::from(rng.gen::<f32>() < (diff - diff.floor()))
         }
         .clamp(-self.population / 2, self.population / 50);
+        if self.population != old_population {
+            notify(
+                observers,
+                current_year,
+                &TickEvent::PopulationChange {
+                    city: self.pos,
+                    old: old_population,
+                    new: self.population,
+                },
+            );
+        }
 
         // Tick all living NPCs
         // IMPORTANT: During the loop, the city's npcs list is empty
         let mut npcs = std::mem::take(&mut self.npcs);
         let mut living_npcs: Vec<&mut Npc> = npcs.iter_mut().filter(|npc| npc.alive).collect();
         mut_loop!(living_npcs => for npc in list {
-            self.tick_npc(npc, rng, current_year, config, items, magic);
+            self.tick_npc(npc, rng, current_year, config, items, magic, observers, passable);
         });
         if living_npcs.len() < 3 {
-            npcs.push(self.generate_npc(rng, current_year, markov_data_npc));
+            let npc = self.generate_npc(rng, current_year, markov_data_npc);
+            notify(
+                observers,
+                current_year,
+                &TickEvent::NpcBirth {
+                    city: self.pos,
+                    npc: &npc,
+                },
+            );
+            npcs.push(npc);
         }
         self.npcs = npcs;
     }
 
-    fn get_traveler_options(npc: &Npc, config: &Config, rng: &mut ThreadRng) -> Vec<usize> {
-        get_adj(npc.pos, 1, config)
-            .iter()
-            .filter_map(|&point| {
-                let dist = distance(point, npc.origin, config);
+    /// The tiles an NPC could travel to next. `passable` says which tiles can be walked on.
+    fn get_traveler_options<R: Rng + ?Sized>(
+        npc: &Npc,
+        config: &Config,
+        passable: &[bool],
+        rng: &mut R,
+    ) -> Vec<usize> {
+        let grid = config.grid();
+        grid.neighbors(npc.pos, 1)
+            .into_iter()
+            .filter(|&point| passable.get(point).copied().unwrap_or(false))
+            .filter(|&point| {
+                let dist = grid.distance(point, npc.origin);
                 if dist == 0.0 {
-                    if rng.gen::<f32>() < ((50.0 - npc.age as f32) / npc.age as f32) {
-                        None
-                    } else {
-                        Some(point)
-                    }
-                } else if dist < 10.0 {
-                    Some(point)
+                    rng.gen::<f32>() >= ((50.0 - npc.age as f32) / npc.age as f32)
                 } else {
-                    None
+                    dist < 10.0
                 }
             })
             .collect()
     }
 
-    fn tick_npc(
+    #[allow(clippy::too_many_arguments)]
+    fn tick_npc<R: Rng + ?Sized>(
         &mut self,
         npc: &mut Npc,
-        rng: &mut ThreadRng,
+        rng: &mut R,
         current_year: u32,
         config: &Config,
         items: &Items,
         magic: &MagicSystem,
+        observers: &[Box<dyn Observer>],
+        passable: &[bool],
     ) {
         npc.age += 1;
         // Die of old age
         if npc.age > 80 {
             npc.alive = false;
+            notify(
+                observers,
+                current_year,
+                &TickEvent::NpcDeath {
+                    city: self.pos,
+                    npc,
+                },
+            );
             return;
         }
         // Traveling
-        let traveler_options = Self::get_traveler_options(npc, config, rng);
+        let traveler_options = Self::get_traveler_options(npc, config, passable, rng);
         if npc.pos != npc.origin && !traveler_options.is_empty() {
             // Continue traveling; unwrap is safe as long as traveler_options isn't empty
             npc.pos = *traveler_options.choose(rng).unwrap();
@@ -321,13 +547,19 @@ impl City {
 
         // Learning / Studying
         if npc.age > 15 {
-            Self::npc_study(rng, npc, current_year);
+            self.npc_study(rng, npc, current_year, observers);
             self.npc_work(rng, npc, items);
             self.npc_magic_work(rng, npc, magic);
         }
     }
 
-    fn npc_study(rng: &mut ThreadRng, npc: &mut Npc, current_year: u32) {
+    fn npc_study<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        npc: &mut Npc,
+        current_year: u32,
+        observers: &[Box<dyn Observer>],
+    ) {
         let study_choices: Vec<u8> = Skill::iter()
             .map(|skill| *npc.skills.entry(skill).or_insert(0) + 1)
             .collect();
@@ -342,6 +574,19 @@ impl City {
             return;
         }
         *npc.skills.get_mut(&choice).unwrap() += 1;
+        let level = npc.skills[&choice];
+        if matches!(level, 2 | 5 | 10) {
+            notify(
+                observers,
+                current_year,
+                &TickEvent::SkillMilestone {
+                    city: self.pos,
+                    npc,
+                    skill: choice,
+                    level,
+                },
+            );
+        }
         match npc.skills.get(&choice) {
             Some(2) => npc.life.push(HistoricalEvent {
                 time: current_year,
@@ -359,7 +604,7 @@ impl City {
         }
     }
 
-    fn npc_work(&mut self, rng: &mut ThreadRng, npc: &mut Npc, items: &Items) {
+    fn npc_work<R: Rng + ?Sized>(&mut self, rng: &mut R, npc: &Npc, items: &Items) {
         macro_rules! produce_goods {
             ($skill: expr, $material_type: expr, $material: expr => $product: expr) => {
                 let mut prod = npc.skills[$skill] as f32 * 100.0;
@@ -369,14 +614,17 @@ impl City {
                         break;
                     }
                     let resource = rng.gen_range(0..$material_type.len());
-                    let resource_usize = $material(resource as u8).to_index(items).unwrap();
-                    let result_usize = $product(resource as u8).to_index(items).unwrap();
+                    let (Some(material), Some(product)) = (
+                        items.index($material(resource as u8)),
+                        items.index($product(resource as u8)),
+                    ) else {
+                        continue;
+                    };
                     let quantity =
-                        std::cmp::min(self.resources.get(resource_usize) as i64, prod as i64)
-                            as f32;
-                    self.resources.add(resource_usize, -quantity);
-                    self.resources.add(result_usize, quantity);
-                    self.production.add(result_usize, quantity);
+                        std::cmp::min(self.resources.get(material) as i64, prod as i64) as f32;
+                    self.resources.add(material, -quantity);
+                    self.resources.add(product, quantity);
+                    self.production.add(product, quantity);
                     prod -= quantity;
                 }
             };
@@ -387,7 +635,8 @@ impl City {
         produce_goods!(&Skill::Gemcutting, items.gems, &Item::Gem => &Item::CutGem);
     }
 
-    fn npc_magic_work(&mut self, rng: &mut ThreadRng, npc: &mut Npc, magic: &MagicSystem) {
+    fn npc_magic_work<R: Rng + ?Sized>(&mut self, rng: &mut R, npc: &mut Npc, magic: &MagicSystem) {
+        let Some(material) = magic.index else { return };
         let mut magic_prod = npc.skills[&Skill::Magic] as f32 * 100.0;
         let magic_types: Vec<&crate::magic::Ability> = magic
             .abilities
@@ -407,10 +656,10 @@ impl City {
             }
             let &magic_type = magic_types.choose(rng).unwrap();
             let quantity = std::cmp::min(
-                (self.resources.get(magic.index.unwrap()) / magic_type.strength as f32) as i64,
+                (self.resources.get(material) / magic_type.strength as f32) as i64,
                 magic_prod as i64,
             ) as f32;
-            self.resources.add(magic.index.unwrap(), -quantity);
+            self.resources.add(material, -quantity);
             match magic_type.ability_type {
                 crate::magic::AbilityType::Homunculus => self.homunculi += quantity as i32,
                 crate::magic::AbilityType::Youth => npc.age -= quantity as u32,
@@ -421,9 +670,9 @@ impl City {
         }
     }
 
-    fn generate_npc(
+    fn generate_npc<R: Rng + ?Sized>(
         &self,
-        rng: &mut ThreadRng,
+        rng: &mut R,
         current_year: u32,
         markov_data_npc: &MarkovData,
     ) -> Npc {
@@ -441,3 +690,136 @@ impl City {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::City;
+    use crate::{
+        magic::MagicSystem,
+        mkv::MarkovCollection,
+        sim::{
+            logging::tests::Recorder, Inventory, Item, ItemType, Observer, PriceOverrides,
+            TickEvent, Want,
+        },
+        Config, Items, SimRng,
+    };
+    use rand::SeedableRng;
+
+    fn kind(name: &str) -> ItemType {
+        ItemType {
+            name: String::from(name),
+            rarity: 1,
+            abundance: 1,
+            value: 1,
+            taming: 0,
+            overrides: PriceOverrides::default(),
+        }
+    }
+
+    fn wheat() -> Items {
+        Items::from_item_types(vec![kind("Wheat")], Vec::new(), Vec::new(), Vec::new())
+    }
+
+    #[test]
+    fn tick_tells_observers_what_happened() {
+        let markov = MarkovCollection::builtin();
+        let mut rng = SimRng::seed_from_u64(1);
+        let items = wheat();
+        let config = Config {
+            population_constant: 1.0,
+            ..Config::default()
+        };
+        let magic = MagicSystem::gen(&mut rng, &markov);
+        // a hundred people gathering only 85 wheat, and one of them about to die of old age
+        let mut gathering = Inventory::default(&items);
+        gathering.set(items.named("Wheat").unwrap(), 10.0);
+        let mut city = City::new(5, String::from("Test"), gathering, HashMap::new(), &items);
+        let mut old = city.generate_npc(&mut rng, 0, &markov.name);
+        old.age = 80;
+        city.npcs.push(old);
+        let recorder = Arc::new(Recorder::default());
+        let observers: Vec<Box<dyn Observer>> = vec![Box::new(recorder.clone())];
+        let passable = vec![true; config.grid().len()];
+        let mut tick = |city: &mut City, year| {
+            let (names, observers) = (&markov.name, &observers);
+            city.tick(
+                &mut rng, year, &config, &items, &magic, names, observers, &passable,
+            );
+        };
+        tick(&mut city, 0);
+        let (died, born) = (&city.npcs[0], &city.npcs[1]);
+        let expected = [
+            TickEvent::Snapshot {
+                city: 5,
+                snapshot: &city.data["0"],
+            },
+            TickEvent::PopulationChange {
+                city: 5,
+                old: 100,
+                new: 85,
+            },
+            TickEvent::NpcDeath { city: 5, npc: died },
+            TickEvent::NpcBirth { city: 5, npc: born },
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|event| (0, format!("{event:?}")))
+            .collect();
+        assert_eq!(recorder.take(), expected);
+        // snapshots only come every `snapshot_interval` years
+        tick(&mut city, 1);
+        assert!(recorder
+            .take()
+            .iter()
+            .all(|(year, event)| *year == 1 && !event.starts_with("Snapshot")));
+    }
+
+    #[test]
+    fn wants_get_used_up() {
+        let markov = MarkovCollection::builtin();
+        let mut rng = SimRng::seed_from_u64(1);
+        let (plants, metals, gems) = (vec![kind("Wheat")], vec![kind("Iron")], vec![kind("Ruby")]);
+        let items = Items::from_item_types(plants, metals, gems, Vec::new());
+        let config = Config {
+            consumption: 0.1,
+            ..Config::default()
+        };
+        let magic = MagicSystem::gen(&mut rng, &markov);
+        // a hundred people who want twice the usual metal goods, with a coin each
+        let culture = HashMap::from([(Want::Goods, 2.0)]);
+        let gathering = Inventory::default(&items);
+        let mut city = City::new(5, String::from("Test"), gathering, culture, &items);
+        let [fish, wheat, goods, jewels] = [
+            Item::Fish,
+            Item::Plant(0),
+            Item::MetalGood(0),
+            Item::CutGem(0),
+        ]
+        .map(|item| items.index(item).unwrap());
+        for (item, amount) in [(fish, 40.0), (wheat, 60.0), (goods, 30.0), (jewels, 30.0)] {
+            city.resources.set(item, amount);
+        }
+        // food is eaten in proportion to the stock; a coin a head makes the city half affluent,
+        // so it wants as many goods as the culture asks and a quarter of its share of jewels
+        assert_eq!(
+            city.demand(100.0, &config, &items),
+            [40.0, 60.0, 0.0, 20.0, 0.0, 2.5]
+        );
+        // the destitute want only half the goods and no jewels at all
+        city.treasury = 0.0;
+        assert_eq!(
+            city.demand(100.0, &config, &items),
+            [40.0, 60.0, 0.0, 10.0, 0.0, 0.0]
+        );
+        city.treasury = 100.0;
+        let passable = vec![true; config.grid().len()];
+        let (names, observers) = (&markov.name, &[]);
+        city.tick(
+            &mut rng, 0, &config, &items, &magic, names, observers, &passable,
+        );
+        let left: Vec<f32> = city.resources.iter().map(|(_, amount)| amount).collect();
+        assert_eq!(left, [0.0, 0.0, 0.0, 10.0, 0.0, 27.5]);
+    }
+}
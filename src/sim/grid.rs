@@ -0,0 +1,355 @@
+use std::ops::Range;
+
+use json::{array, JsonValue};
+use strum_macros::{AsRefStr, EnumIter};
+
+use crate::jsonize::json_int;
+
+/// Which edges of the map join up with the opposite edge
+#[derive(Debug, Clone, Copy, Default, AsRefStr, PartialEq, Eq, EnumIter)]
+pub enum Topology {
+    /// Every edge is a hard boundary, ringed with ocean
+    #[default]
+    Flat,
+    /// The east and west edges join
+    Cylinder,
+    /// The east and west edges join, as do the north and south edges
+    Torus,
+}
+
+impl Topology {
+    #[must_use]
+    pub const fn wraps_x(self) -> bool {
+        matches!(self, Self::Cylinder | Self::Torus)
+    }
+
+    #[must_use]
+    pub const fn wraps_y(self) -> bool {
+        matches!(self, Self::Torus)
+    }
+}
+
+/// The shape of the map's tiles
+#[derive(Debug, Clone, Copy, Default, AsRefStr, PartialEq, Eq, EnumIter)]
+pub enum TileShape {
+    /// Squares, with eight neighbors each
+    #[default]
+    Square,
+    /// Pointy-topped hexes, with six neighbors each. Odd rows sit half a tile to the east.
+    /// A torus of hexes needs an even height for the rows to line up across the seam; see
+    /// [`Grid::seams_line_up`].
+    Hex,
+}
+
+/// A tile's position on the world map
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct Coord {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Coord {
+    #[must_use]
+    pub const fn new(x: usize, y: usize) -> Self {
+        Self { x, y }
+    }
+}
+
+impl From<Coord> for JsonValue {
+    fn from(coord: Coord) -> Self {
+        array![coord.x, coord.y]
+    }
+}
+
+/// The shape of the world map
+///
+/// Tiles are stored row by row, so a tile's index is `x + y * width`. This converts between
+/// the two and keeps every lookup in bounds, wrapping around the edges that the topology joins.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    topology: Topology,
+    shape: TileShape,
+}
+
+impl Grid {
+    #[must_use]
+    pub const fn new(width: usize, height: usize, topology: Topology, shape: TileShape) -> Self {
+        Self {
+            width,
+            height,
+            topology,
+            shape,
+        }
+    }
+
+    #[must_use]
+    pub const fn topology(self) -> Topology {
+        self.topology
+    }
+
+    #[must_use]
+    pub const fn shape(self) -> TileShape {
+        self.shape
+    }
+
+    #[must_use]
+    pub const fn width(self) -> usize {
+        self.width
+    }
+
+    #[must_use]
+    pub const fn height(self) -> usize {
+        self.height
+    }
+
+    /// Whether the rows meet up where the topology joins the top and bottom edges. Hex rows
+    /// alternate in offset, so the last and first rows of a torus only fit together when the
+    /// height is even.
+    #[must_use]
+    pub const fn seams_line_up(self) -> bool {
+        !(self.topology.wraps_y() && matches!(self.shape, TileShape::Hex))
+            || self.height.is_multiple_of(2)
+    }
+
+    /// The number of tiles
+    #[must_use]
+    pub const fn len(self) -> usize {
+        self.width * self.height
+    }
+
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every tile index, row by row
+    #[must_use]
+    pub const fn indices(self) -> Range<usize> {
+        0..self.len()
+    }
+
+    #[must_use]
+    pub const fn contains(self, coord: Coord) -> bool {
+        coord.x < self.width && coord.y < self.height
+    }
+
+    #[must_use]
+    pub const fn coord(self, index: usize) -> Coord {
+        Coord {
+            x: index % self.width,
+            y: index / self.width,
+        }
+    }
+
+    /// The index of `coord`, or `None` if it's off the map
+    #[must_use]
+    pub const fn index(self, coord: Coord) -> Option<usize> {
+        if self.contains(coord) {
+            Some(coord.x + coord.y * self.width)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the tile is on an edge of the map that doesn't wrap around
+    #[must_use]
+    pub const fn is_edge(self, index: usize) -> bool {
+        let Coord { x, y } = self.coord(index);
+        (!self.topology.wraps_x() && (x == 0 || x + 1 == self.width))
+            || (!self.topology.wraps_y() && (y == 0 || y + 1 == self.height))
+    }
+
+    /// The tile `(dx, dy)` away from `from`, wrapping around joined edges. `None` if that
+    /// crosses an edge that doesn't wrap.
+    #[must_use]
+    pub fn step(self, from: Coord, dx: isize, dy: isize) -> Option<usize> {
+        let x = wrap(from.x, dx, self.width, self.topology.wraps_x())?;
+        let y = wrap(from.y, dy, self.height, self.topology.wraps_y())?;
+        self.index(Coord::new(x, y))
+    }
+
+    /// The shortest offset from `a` to `b`, going around joined edges if that's shorter
+    #[must_use]
+    pub fn offset(self, a: usize, b: usize) -> (isize, isize) {
+        let (a, b) = (self.coord(a), self.coord(b));
+        let dx = shortest(a.x, b.x, self.width, self.topology.wraps_x());
+        let dy = shortest(a.y, b.y, self.height, self.topology.wraps_y());
+        match self.shape {
+            TileShape::Square => (dx, dy),
+            // the straight-line shortcut isn't always the fewest hexes, so try every copy of `b`
+            TileShape::Hex => {
+                let copies = |delta: isize, size: usize, wraps: bool| {
+                    let size = size as isize;
+                    if wraps {
+                        vec![delta, delta - size, delta + size]
+                    } else {
+                        vec![delta]
+                    }
+                };
+                let from = (a.x as isize, a.y as isize);
+                copies(dx, self.width, self.topology.wraps_x())
+                    .into_iter()
+                    .flat_map(|dx| {
+                        copies(dy, self.height, self.topology.wraps_y())
+                            .into_iter()
+                            .map(move |dy| (dx, dy))
+                    })
+                    .min_by_key(|&(dx, dy)| hex_steps(from, (from.0 + dx, from.1 + dy)))
+                    .unwrap_or((dx, dy))
+            }
+        }
+    }
+
+    /// The tiles around `center`. For squares, a radius of 0 gives the four orthogonal neighbors;
+    /// otherwise it's every tile in the square of that radius. For hexes it's every tile within
+    /// that many steps, and a radius of 0 is the same as 1. `center` itself, tiles off the edge
+    /// of the map and repeats on a small wrapped map are left out.
+    #[must_use]
+    pub fn neighbors(self, index: usize, radius: usize) -> Vec<usize> {
+        let center = self.coord(index);
+        let offsets: Vec<(isize, isize)> = match self.shape {
+            TileShape::Square if radius == 0 => vec![(1, 0), (-1, 0), (0, 1), (0, -1)],
+            TileShape::Square => {
+                let radius = radius as isize;
+                (-radius..=radius)
+                    .flat_map(|dx| (-radius..=radius).map(move |dy| (dx, dy)))
+                    .filter(|&offset| offset != (0, 0))
+                    .collect()
+            }
+            TileShape::Hex => {
+                let radius = radius.max(1) as isize;
+                let from = (center.x as isize, center.y as isize);
+                (-radius..=radius)
+                    .flat_map(|dx| (-radius..=radius).map(move |dy| (dx, dy)))
+                    .filter(|&(dx, dy)| {
+                        (dx, dy) != (0, 0)
+                            && hex_steps(from, (from.0 + dx, from.1 + dy)) <= radius as usize
+                    })
+                    .collect()
+            }
+        };
+        // only a window wider than the map can wrap around onto the same tile twice
+        let may_repeat = 2 * radius + 1 > self.width.min(self.height);
+        let mut neighbors = Vec::with_capacity(offsets.len());
+        for (dx, dy) in offsets {
+            if let Some(tile) = self.step(center, dx, dy) {
+                if tile != index && !(may_repeat && neighbors.contains(&tile)) {
+                    neighbors.push(tile);
+                }
+            }
+        }
+        neighbors
+    }
+
+    /// Distance between two tiles, the short way around joined edges. Squares measure in a
+    /// straight line; hexes count the steps between them.
+    #[must_use]
+    pub fn distance(self, a: usize, b: usize) -> f32 {
+        let (dx, dy) = self.offset(a, b);
+        match self.shape {
+            TileShape::Square => ((dx * dx + dy * dy) as f32).sqrt(),
+            TileShape::Hex => {
+                let from = self.coord(a);
+                let from = (from.x as isize, from.y as isize);
+                hex_steps(from, (from.0 + dx, from.1 + dy)) as f32
+            }
+        }
+    }
+
+    /// Read a tile from a `[x, y]` array, if it's on the map
+    #[must_use]
+    pub fn dejsonize_tile(self, src: &JsonValue) -> Option<usize> {
+        let JsonValue::Array(coords) = src else { return None };
+        let x = json_int(coords.first()?)?;
+        let y = json_int(coords.get(1)?)?;
+        self.index(Coord::new(x.try_into().ok()?, y.try_into().ok()?))
+    }
+}
+
+const fn wrap(position: usize, delta: isize, size: usize, wraps: bool) -> Option<usize> {
+    if wraps {
+        Some((position as isize + delta).rem_euclid(size as isize) as usize)
+    } else {
+        position.checked_add_signed(delta)
+    }
+}
+
+const fn shortest(from: usize, to: usize, size: usize, wraps: bool) -> isize {
+    let delta = to as isize - from as isize;
+    let size = size as isize;
+    if wraps && delta.abs() * 2 > size {
+        delta - size * delta.signum()
+    } else {
+        delta
+    }
+}
+
+/// Steps between two hexes, converting from offset coordinates to axial ones
+const fn hex_steps(a: (isize, isize), b: (isize, isize)) -> usize {
+    let dq = (b.0 - (b.1 - b.1.rem_euclid(2)) / 2) - (a.0 - (a.1 - a.1.rem_euclid(2)) / 2);
+    let dr = b.1 - a.1;
+    (dq.unsigned_abs() + dr.unsigned_abs() + (dq + dr).unsigned_abs()) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Grid, TileShape, Topology};
+
+    #[test]
+    fn hex_torus_needs_an_even_height() {
+        let odd = Grid::new(4, 3, Topology::Torus, TileShape::Hex);
+        let even = Grid::new(4, 4, Topology::Torus, TileShape::Hex);
+        assert!(!odd.seams_line_up());
+        assert!(even.seams_line_up());
+        assert!(Grid::new(4, 3, Topology::Cylinder, TileShape::Hex).seams_line_up());
+        assert!(Grid::new(4, 3, Topology::Torus, TileShape::Square).seams_line_up());
+        // where the rows line up, every tile is its neighbors' neighbor
+        let symmetric = |grid: Grid| {
+            grid.indices().all(|tile| {
+                grid.neighbors(tile, 1)
+                    .iter()
+                    .all(|&next| grid.neighbors(next, 1).contains(&tile))
+            })
+        };
+        assert!(symmetric(even));
+        assert!(!symmetric(odd));
+    }
+
+    #[test]
+    fn neighbors_stay_on_the_map() {
+        let grid = Grid::new(4, 3, Topology::Flat, TileShape::Square);
+        assert_eq!(grid.neighbors(0, 0), vec![1, 4]);
+        assert_eq!(grid.neighbors(11, 0), vec![10, 7]);
+        assert_eq!(grid.neighbors(0, 1), vec![4, 1, 5]);
+        assert_eq!(grid.neighbors(5, 1).len(), 8);
+        assert!(grid.neighbors(3, 2).iter().all(|&tile| tile < grid.len()));
+    }
+
+    #[test]
+    fn wrapped_neighbors_cross_the_seam() {
+        let grid = Grid::new(4, 3, Topology::Cylinder, TileShape::Square);
+        assert_eq!(grid.neighbors(0, 0), vec![1, 3, 4]);
+        assert_eq!(grid.offset(0, 3), (-1, 0));
+        assert!(grid.is_edge(1) && !grid.is_edge(4) && !grid.is_edge(7));
+        let grid = Grid::new(4, 3, Topology::Torus, TileShape::Square);
+        assert_eq!(grid.neighbors(0, 0), vec![1, 3, 4, 8]);
+        assert_eq!(grid.offset(0, 11), (-1, -1));
+        assert_eq!(grid.neighbors(5, 2).len(), 11);
+        assert!((0..grid.len()).all(|tile| !grid.is_edge(tile)));
+    }
+
+    #[test]
+    fn hexes_have_six_neighbors() {
+        let grid = Grid::new(5, 5, Topology::Flat, TileShape::Hex);
+        // (2, 2) is on an even row, (2, 1) on an odd one
+        assert_eq!(grid.neighbors(12, 1), vec![6, 11, 16, 7, 17, 13]);
+        assert_eq!(grid.neighbors(7, 1), vec![6, 2, 12, 3, 8, 13]);
+        assert_eq!(grid.neighbors(12, 2).len(), 18);
+        assert_eq!(grid.offset(10, 14), (4, 0));
+        let grid = Grid::new(6, 4, Topology::Torus, TileShape::Hex);
+        assert_eq!(grid.offset(0, 5), (-1, 0));
+        assert_eq!(grid.neighbors(0, 1).len(), 6);
+    }
+}
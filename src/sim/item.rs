@@ -1,9 +1,7 @@
-use std::{
-    collections::HashMap,
-    slice::{Iter, IterMut},
-};
+use std::collections::HashMap;
 
 use json::JsonValue;
+use strum_macros::{AsRefStr, EnumIter};
 
 use crate::{
     jsonize::{json_float, Jsonizable},
@@ -17,6 +15,97 @@ pub struct ItemType {
     pub abundance: u8,
     pub value: u8,
     pub taming: u8,
+    /// Pricing that the items file sets instead of deriving it from the numbers above
+    pub overrides: PriceOverrides,
+}
+
+impl ItemType {
+    /// How an item made from this type is priced, at `markup` times the raw material's price.
+    /// The base price follows the value, how far it can swing follows the rarity, and how
+    /// quickly it swings follows the abundance, unless the items file says otherwise.
+    #[must_use]
+    pub fn pricing(&self, markup: f32) -> Pricing {
+        Pricing {
+            base: markup
+                * self
+                    .overrides
+                    .price
+                    .unwrap_or_else(|| f32::from(self.value.max(1)) / 2.0),
+            scarcity: self
+                .overrides
+                .scarcity
+                .unwrap_or_else(|| f32::from(self.rarity).mul_add(0.5, 1.0)),
+            elasticity: self
+                .overrides
+                .elasticity
+                .unwrap_or_else(|| f32::from(self.abundance).mul_add(0.1, 0.5)),
+        }
+    }
+}
+
+/// Parts of an item type's [`Pricing`] given outright, as `key=value` after its numbers in an
+/// items file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PriceOverrides {
+    /// The raw material's base price
+    pub price: Option<f32>,
+    pub scarcity: Option<f32>,
+    pub elasticity: Option<f32>,
+}
+
+impl PriceOverrides {
+    /// Set the override named `key`. `None` if there's no such override or `value` isn't
+    /// positive.
+    pub fn set(&mut self, key: &str, value: f32) -> Option<()> {
+        if value.is_nan() || value <= 0.0 {
+            return None;
+        }
+        *match key.trim() {
+            "price" => &mut self.price,
+            "scarcity" => &mut self.scarcity,
+            "elasticity" => &mut self.elasticity,
+            _ => return None,
+        } = Some(value);
+        Some(())
+    }
+
+    /// The overrides that are set, by name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, f32)> {
+        [
+            ("price", self.price),
+            ("scarcity", self.scarcity),
+            ("elasticity", self.elasticity),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+    }
+}
+
+/// How a city prices an item from what it wants of it and what it has
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pricing {
+    /// The price when a city has as much as it wants
+    pub base: f32,
+    /// How many times the base price a shortage can drive the price up to, and the fraction
+    /// of it a glut can drive it down to
+    pub scarcity: f32,
+    /// How strongly the price follows the ratio of demand to stock
+    pub elasticity: f32,
+}
+
+impl Pricing {
+    /// Fish isn't in the items files, so it's priced like a common plant
+    pub const FISH: Self = Self {
+        base: 1.0,
+        scarcity: 2.0,
+        elasticity: 0.6,
+    };
+
+    #[must_use]
+    pub fn price(&self, demand: f32, stock: f32) -> f32 {
+        let ratio = ((demand.max(0.0) + 1.0) / (stock.max(0.0) + 1.0)).powf(self.elasticity);
+        self.base * ratio.clamp(self.scarcity.recip(), self.scarcity)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
@@ -32,11 +121,55 @@ pub enum Item {
     Meat(u8),
 }
 
+/// What people want besides food
+#[derive(Debug, Clone, Copy, AsRefStr, EnumIter, Eq, Hash, PartialEq)]
+pub enum Want {
+    /// Metal goods
+    Goods,
+    /// Cut gems, which people only buy once they're well off
+    Luxuries,
+    /// Tame animals, to draw ploughs and carts
+    Animals,
+}
+
 impl Item {
-    pub fn is_food(&self) -> bool {
+    #[must_use]
+    pub const fn is_food(self) -> bool {
         matches!(self, Self::Fish | Self::Plant(_) | Self::Meat(_))
     }
 
+    /// What people want the item for, if it's something they use up besides food
+    #[must_use]
+    pub const fn want(self) -> Option<Want> {
+        match self {
+            Self::MetalGood(_) => Some(Want::Goods),
+            Self::CutGem(_) => Some(Want::Luxuries),
+            Self::TameAnimal(_) => Some(Want::Animals),
+            _ => None,
+        }
+    }
+
+    /// How the item is priced. Worked goods cost a few times their raw material, and tame
+    /// animals more the harder they are to tame.
+    #[must_use]
+    pub fn pricing(self, items: &Items) -> Pricing {
+        match self {
+            Self::Fish => Pricing::FISH,
+            Self::Plant(item) => items.plants[item as usize].pricing(1.0),
+            Self::Metal(item) => items.metals[item as usize].pricing(1.0),
+            Self::MetalGood(item) => items.metals[item as usize].pricing(4.0),
+            Self::Gem(item) => items.gems[item as usize].pricing(1.0),
+            Self::CutGem(item) => items.gems[item as usize].pricing(10.0),
+            Self::WildAnimal(item) => items.animals[item as usize].pricing(1.0),
+            Self::TameAnimal(item) => {
+                let animal = &items.animals[item as usize];
+                animal.pricing(f32::from(animal.taming).mul_add(0.5, 1.0))
+            }
+            Self::Meat(item) => items.animals[item as usize].pricing(2.0),
+        }
+    }
+
+    #[must_use]
     pub fn to_string(self, items: &Items) -> String {
         match self {
             Self::Fish => String::from("Fish"),
@@ -50,9 +183,21 @@ impl Item {
             Self::Meat(item) => format!("{} Meat", items.animals[item as usize].name),
         }
     }
+}
+
+/// An item's position in the [`Items`] registry, and so in every [`Inventory`].
+/// Only the registry hands these out.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct ItemIndex(usize);
+
+impl ItemIndex {
+    pub(crate) const fn new(index: usize) -> Self {
+        Self(index)
+    }
 
-    pub fn to_index(self, items: &Items) -> Option<usize> {
-        items.all.iter().position(|&m| m == self)
+    #[must_use]
+    pub const fn get(self) -> usize {
+        self.0
     }
 }
 
@@ -60,35 +205,51 @@ impl Item {
 pub struct Inventory(Vec<f32>);
 
 impl Inventory {
+    #[must_use]
     pub fn default(items: &Items) -> Self {
-        Self(vec![0.0; items.all.len()])
+        Self(vec![0.0; items.len()])
     }
 
-    pub fn get(&self, i: usize) -> f32 {
-        match self.0.get(i) {
+    #[must_use]
+    pub fn get(&self, item: ItemIndex) -> f32 {
+        match self.0.get(item.0) {
             None => 0.0,
             Some(&res) => res,
         }
     }
 
-    pub fn set(&mut self, i: usize, v: f32) {
-        assert!(i < self.0.len());
+    /// Set the amount of `item`, unless `v` is NaN
+    ///
+    /// # Panics
+    ///
+    /// If `item` isn't one of the items the inventory was made for
+    pub fn set(&mut self, item: ItemIndex, v: f32) {
+        assert!(item.0 < self.0.len());
         if !v.is_nan() {
-            self.0[i] = v;
+            self.0[item.0] = v;
         }
     }
 
-    pub fn add(&mut self, i: usize, v: f32) {
-        assert!(!v.is_nan(), "{i} => {v}");
-        self.set(i, self.get(i) + v);
+    /// # Panics
+    ///
+    /// If `v` is NaN, or `item` isn't one of the items the inventory was made for
+    pub fn add(&mut self, item: ItemIndex, v: f32) {
+        assert!(!v.is_nan(), "{item:?} => {v}");
+        self.set(item, self.get(item) + v);
     }
 
-    pub fn iter(&self) -> Iter<'_, f32> {
-        self.0.iter()
+    pub fn iter(&self) -> impl Iterator<Item = (ItemIndex, f32)> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(index, &amount)| (ItemIndex(index), amount))
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<'_, f32> {
-        self.0.iter_mut()
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ItemIndex, &mut f32)> {
+        self.0
+            .iter_mut()
+            .enumerate()
+            .map(|(index, amount)| (ItemIndex(index), amount))
     }
 }
 
@@ -102,12 +263,11 @@ impl Jsonizable for Inventory {
     fn jsonize(&self, _config: &Config, items: &Items) -> JsonValue {
         JsonValue::from(
             self.iter()
-                .enumerate()
-                .filter_map(|(index, &amount)| {
+                .filter_map(|(index, amount)| {
                     if amount == 0.0 {
                         None
                     } else {
-                        Some((items.all[index].to_string(items), amount))
+                        Some((items.item(index).to_string(items), amount))
                     }
                 })
                 .collect::<HashMap<String, f32>>(),
@@ -118,14 +278,82 @@ impl Jsonizable for Inventory {
         let JsonValue::Object(object) = src else { return None; };
         Some(Self(
             items
-                .all
                 .iter()
-                .map(|item| {
-                    object.get(&item.to_string(items)).map_or(0.0, |jsonvalue| {
-                        json_float(jsonvalue, 2).unwrap_or_default()
-                    })
+                .map(|(_, item)| {
+                    object
+                        .get(&item.to_string(items))
+                        .map_or(0.0, |jsonvalue| json_float(jsonvalue).unwrap_or_default())
                 })
                 .collect(),
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Item, Pricing};
+    use crate::{Items, SuperJsonizable};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn price_follows_demand_within_scarcity() {
+        let pricing = Pricing {
+            base: 10.0,
+            scarcity: 3.0,
+            elasticity: 1.0,
+        };
+        assert!(close(pricing.price(5.0, 5.0), 10.0));
+        assert!(close(pricing.price(3.0, 1.0), 20.0));
+        assert!(close(pricing.price(1.0, 3.0), 5.0));
+        // the price stops at scarcity times the base either way
+        assert!(close(pricing.price(1000.0, 0.0), 30.0));
+        assert!(close(pricing.price(0.0, 1000.0), 10.0 / 3.0));
+        // negative amounts count as none
+        assert!(close(pricing.price(-5.0, -5.0), 10.0));
+        // a lower elasticity dampens the swing
+        let sticky = Pricing {
+            elasticity: 0.5,
+            ..pricing
+        };
+        assert!(close(sticky.price(3.0, 0.0), 20.0));
+    }
+
+    #[test]
+    fn prices_follow_item_data() {
+        let items = Items::s_dejsonize(
+            &json::parse(
+                r#"{
+                    "Plants": {},
+                    "Metals": {},
+                    "Animals": {},
+                    "Gems": {
+                        "Quartz": [1, 1, 2],
+                        "Diamond": [9, 9, 18],
+                        "Glass": [1, 1, 2, {"price": 7}]
+                    }
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let pricing = |item| items.pricing(items.index(item).unwrap());
+        let (quartz, diamond, glass) = (Item::CutGem(0), Item::CutGem(1), Item::CutGem(2));
+        assert!(pricing(diamond).base > pricing(quartz).base * 5.0);
+        assert!(close(pricing(Item::Gem(2)).base, 7.0));
+        assert!(close(pricing(glass).base, 70.0));
+        // shortages raise the price, up to the limit scarcity sets
+        let diamond = pricing(diamond);
+        assert!(diamond.price(10.0, 0.0) > diamond.price(10.0, 10.0));
+        assert!(diamond.price(10.0, 10.0) > diamond.price(10.0, 100.0));
+        assert!(close(
+            diamond.price(1e9, 0.0),
+            diamond.base * diamond.scarcity
+        ));
+        // and the override survives a save
+        let saved = Items::s_dejsonize(&items.s_jsonize()).unwrap();
+        assert!(close(saved.pricing(items.index(glass).unwrap()).base, 70.0));
+    }
+}
@@ -1,10 +1,12 @@
 use json::{object, JsonValue};
 
 use crate::{
-    jsonize::{json_int, json_string, Jsonizable, SuperJsonizable},
-    Config, Inventory, Items,
+    jsonize::{json_float, json_int, json_string, Jsonizable, SuperJsonizable},
+    Config, Inventory, ItemIndex, Items, Npc, Skill,
 };
 
+use super::RouteChange;
+
 #[derive(Debug, Clone)]
 pub struct HistoricalEvent {
     pub time: u32,
@@ -32,25 +34,154 @@ impl SuperJsonizable for HistoricalEvent {
 #[derive(Debug, Clone)]
 pub struct Snapshot {
     pub population: i32,
+    pub homunculi: i32,
+    pub living_npcs: i32,
+    /// Totals since the last snapshot
     pub production: Inventory,
     pub imports: Inventory,
+    pub exports: Inventory,
+    /// Stockpiles and prices at the time of the snapshot
+    pub resources: Inventory,
+    pub economy: Inventory,
+    pub treasury: f32,
+    /// Coins earned from exports and paid for imports since the last snapshot
+    pub earned: f32,
+    pub spent: f32,
 }
 
 impl Jsonizable for Snapshot {
     fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
         object! {
             population: self.population,
+            homunculi: self.homunculi,
+            living_npcs: self.living_npcs,
             production: self.production.jsonize(config, items),
-            imports: self.imports.jsonize(config, items)
+            imports: self.imports.jsonize(config, items),
+            exports: self.exports.jsonize(config, items),
+            resources: self.resources.jsonize(config, items),
+            economy: self.economy.jsonize(config, items),
+            treasury: self.treasury,
+            earned: self.earned,
+            spent: self.spent
         }
     }
 
     fn dejsonize(src: &JsonValue, config: &Config, items: &Items) -> Option<Self> {
         let JsonValue::Object(object) = src else { return None };
+        // snapshots from older saves only have population, production and imports, and no coins
+        let inventory = |key: &str| {
+            object
+                .get(key)
+                .and_then(|inventory| Inventory::dejsonize(inventory, config, items))
+                .unwrap_or_else(|| Inventory::default(items))
+        };
         Some(Self {
             population: json_int(object.get("population")?)?,
+            homunculi: object.get("homunculi").and_then(json_int).unwrap_or(0),
+            living_npcs: object.get("living_npcs").and_then(json_int).unwrap_or(0),
             production: Inventory::dejsonize(object.get("production")?, config, items)?,
             imports: Inventory::dejsonize(object.get("imports")?, config, items)?,
+            exports: inventory("exports"),
+            resources: inventory("resources"),
+            economy: inventory("economy"),
+            treasury: object.get("treasury").and_then(json_float).unwrap_or(0.0),
+            earned: object.get("earned").and_then(json_float).unwrap_or(0.0),
+            spent: object.get("spent").and_then(json_float).unwrap_or(0.0),
         })
     }
 }
+
+/// Something notable that happened while the world was ticking
+#[derive(Debug, Clone, Copy)]
+pub enum TickEvent<'a> {
+    NpcBirth {
+        city: usize,
+        npc: &'a Npc,
+    },
+    NpcDeath {
+        city: usize,
+        npc: &'a Npc,
+    },
+    /// An NPC reached level 2, 5 or 10 in a skill
+    SkillMilestone {
+        city: usize,
+        npc: &'a Npc,
+        skill: Skill,
+        level: u8,
+    },
+    /// `buyer`, at one end of `route`, bought `amount` of `item` from the other end, paying
+    /// `price` for each
+    Trade {
+        route: (usize, usize),
+        buyer: usize,
+        item: ItemIndex,
+        amount: f32,
+        price: f32,
+    },
+    /// A trade route opened or closed, for the reason given
+    RouteChanged {
+        route: (usize, usize),
+        change: RouteChange,
+    },
+    PopulationChange {
+        city: usize,
+        old: i32,
+        new: i32,
+    },
+    Snapshot {
+        city: usize,
+        snapshot: &'a Snapshot,
+    },
+}
+
+/// Receives notable events from `World::tick`. Cities tick in parallel, so events from
+/// different cities can arrive in any order and from any thread.
+pub trait Observer: Send + Sync {
+    fn observe(&self, year: u32, event: &TickEvent);
+}
+
+pub fn notify(observers: &[Box<dyn Observer>], year: u32, event: &TickEvent) {
+    for observer in observers {
+        observer.observe(year, event);
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{notify, Observer, TickEvent};
+
+    /// Keeps every event it's told about, written out with the year it happened in
+    #[derive(Default)]
+    pub struct Recorder(Mutex<Vec<(u32, String)>>);
+
+    impl Recorder {
+        pub fn take(&self) -> Vec<(u32, String)> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl Observer for Arc<Recorder> {
+        fn observe(&self, year: u32, event: &TickEvent) {
+            self.0.lock().unwrap().push((year, format!("{event:?}")));
+        }
+    }
+
+    #[test]
+    fn notify_reaches_every_observer() {
+        let (first, second) = (Arc::new(Recorder::default()), Arc::new(Recorder::default()));
+        let observers: Vec<Box<dyn Observer>> =
+            vec![Box::new(first.clone()), Box::new(second.clone())];
+        let event = TickEvent::PopulationChange {
+            city: 3,
+            old: 100,
+            new: 98,
+        };
+        notify(&observers, 12, &event);
+        notify(&[], 13, &event);
+        let expected = vec![(12, format!("{event:?}"))];
+        assert_eq!(first.take(), expected);
+        assert_eq!(second.take(), expected);
+    }
+}
@@ -0,0 +1,640 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+};
+
+use json::{array, object, JsonValue};
+use strum::IntoEnumIterator;
+use strum_macros::{AsRefStr, EnumIter};
+
+use crate::{
+    jsonize::{json_float, json_int, json_string, Jsonizable},
+    Config, Items,
+};
+
+use super::{Biome, Grid, Inventory, ItemIndex, Water, Waters};
+
+/// How goods get from one end of a route to the other
+#[derive(Debug, Clone, Copy, AsRefStr, EnumIter, PartialEq, Eq)]
+pub enum RouteKind {
+    /// Overland, and along rivers
+    Land,
+    /// By ship, from one port to another
+    Sea,
+}
+
+impl RouteKind {
+    /// The cost of crossing one tile of `biome`. `None` if this kind of route can't cross it.
+    #[must_use]
+    pub fn cost(self, biome: &Biome, water: Option<Water>) -> Option<f32> {
+        match self {
+            // boats carry goods along rivers and across lakes
+            Self::Land if biome.passable && water.is_some() => {
+                Some(biome.travel_cost / Waters::TRADE_BONUS)
+            }
+            Self::Land if biome.passable => Some(biome.travel_cost),
+            Self::Sea if !biome.passable => Some(biome.travel_cost),
+            _ => None,
+        }
+    }
+}
+
+/// The way trade goes between two cities
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeRoute {
+    pub kind: RouteKind,
+    /// Every tile from one city to the other, both included
+    pub path: Vec<usize>,
+    /// How hard the path is to travel, adding up the cost of every step
+    pub cost: f32,
+    /// How many years goods have gone along the route
+    pub trades: i32,
+    /// How established the route is, from 1 for a busy one down to 0, when it closes
+    pub strength: f32,
+    /// How many goods have gone along the route, either way
+    pub carried: f32,
+    /// The coins paid for them
+    pub value: f32,
+}
+
+impl TradeRoute {
+    /// How established a newly opened route is
+    pub const OPENING_STRENGTH: f32 = 0.5;
+
+    /// How many goods the route can carry in a year. A newly opened route that costs `reach`
+    /// carries half of `volume`, and longer, rougher ones less. Established routes, with their
+    /// roads, inns and harbors built up, carry half as much again.
+    #[must_use]
+    pub fn capacity(&self, volume: f32, reach: f32) -> f32 {
+        volume * reach / (reach + self.cost) * (0.5 + self.strength)
+    }
+
+    /// Count a year's trades along the route, `carried` goods worth `value` coins, which make
+    /// it `growth` more established
+    pub fn record_trade(&mut self, growth: f32, carried: f32, value: f32) {
+        self.trades += 1;
+        self.strength = (self.strength + growth).min(1.0);
+        self.carried += carried;
+        self.value += value;
+    }
+}
+
+impl Jsonizable for TradeRoute {
+    fn jsonize(&self, config: &Config, _items: &Items) -> JsonValue {
+        object! {
+            kind: self.kind.as_ref(),
+            cost: self.cost,
+            trades: self.trades,
+            strength: self.strength,
+            carried: self.carried,
+            value: self.value,
+            path: self
+                .path
+                .iter()
+                .map(|&tile| JsonValue::from(config.grid().coord(tile)))
+                .collect::<Vec<_>>()
+        }
+    }
+
+    fn dejsonize(src: &JsonValue, config: &Config, _items: &Items) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        let Some(JsonValue::Array(path)) = object.get("path") else { return None };
+        let kind = json_string(object.get("kind")?)?;
+        Some(Self {
+            kind: RouteKind::iter().find(|k| k.as_ref() == kind)?,
+            path: {
+                let mut tiles = Vec::new();
+                for tile in path {
+                    tiles.push(config.grid().dejsonize_tile(tile)?);
+                }
+                tiles
+            },
+            cost: json_float(object.get("cost")?)?,
+            trades: json_int(object.get("trades")?)?,
+            // routes from older saves never changed, so they start out like new ones
+            strength: object
+                .get("strength")
+                .and_then(json_float)
+                .unwrap_or(Self::OPENING_STRENGTH),
+            // or kept track of what went along them
+            carried: object.get("carried").and_then(json_float).unwrap_or(0.0),
+            value: object.get("value").and_then(json_float).unwrap_or(0.0),
+        })
+    }
+}
+
+/// Why a trade route opened or closed
+#[derive(Debug, Clone, Copy, AsRefStr, EnumIter, PartialEq, Eq)]
+pub enum RouteChange {
+    /// A city with no partners, like a newly founded one, went looking for one
+    Isolation,
+    /// A city grew prosperous enough to look for another partner
+    Prosperity,
+    /// A traveler from one city came across the other
+    Discovery,
+    /// Too little trade went along the route to keep it going
+    Disuse,
+    /// Monsters near the path scared the traders off
+    Danger,
+    /// A city at one end of the route died out
+    Ruin,
+}
+
+impl RouteChange {
+    /// Whether the change opened a route, rather than closing one
+    #[must_use]
+    pub const fn opens(self) -> bool {
+        matches!(self, Self::Isolation | Self::Prosperity | Self::Discovery)
+    }
+}
+
+/// A trade route opening or closing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteEvent {
+    pub year: u32,
+    /// The cities at either end, in the same order as the route's key
+    pub route: (usize, usize),
+    pub change: RouteChange,
+}
+
+impl Jsonizable for RouteEvent {
+    fn jsonize(&self, config: &Config, _items: &Items) -> JsonValue {
+        let grid = config.grid();
+        object! {
+            year: self.year,
+            route: array![grid.coord(self.route.0), grid.coord(self.route.1)],
+            change: self.change.as_ref()
+        }
+    }
+
+    fn dejsonize(src: &JsonValue, config: &Config, _items: &Items) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        let Some(JsonValue::Array(route)) = object.get("route") else { return None };
+        let change = json_string(object.get("change")?)?;
+        Some(Self {
+            year: json_int(object.get("year")?)? as u32,
+            route: (
+                config.grid().dejsonize_tile(route.first()?)?,
+                config.grid().dejsonize_tile(route.get(1)?)?,
+            ),
+            change: RouteChange::iter().find(|c| c.as_ref() == change)?,
+        })
+    }
+}
+
+/// A good bought along a route, as kept in the world's trade ledger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trade {
+    pub year: u32,
+    /// The cities at either end, in the same order as the route's key
+    pub route: (usize, usize),
+    /// The end of the route that bought the goods
+    pub buyer: usize,
+    pub item: ItemIndex,
+    pub amount: f32,
+    /// The coins paid for each
+    pub price: f32,
+}
+
+impl Trade {
+    /// The end of the route that sold the goods
+    #[must_use]
+    pub const fn seller(&self) -> usize {
+        if self.buyer == self.route.0 {
+            self.route.1
+        } else {
+            self.route.0
+        }
+    }
+
+    /// The coins paid for all of the goods
+    #[must_use]
+    pub fn value(&self) -> f32 {
+        self.amount * self.price
+    }
+}
+
+impl Jsonizable for Trade {
+    fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
+        let grid = config.grid();
+        object! {
+            year: self.year,
+            route: array![grid.coord(self.route.0), grid.coord(self.route.1)],
+            buyer: grid.coord(self.buyer),
+            item: items.item(self.item).to_string(items),
+            amount: self.amount,
+            price: self.price
+        }
+    }
+
+    fn dejsonize(src: &JsonValue, config: &Config, items: &Items) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        let Some(JsonValue::Array(route)) = object.get("route") else { return None };
+        let grid = config.grid();
+        Some(Self {
+            year: json_int(object.get("year")?)? as u32,
+            route: (
+                grid.dejsonize_tile(route.first()?)?,
+                grid.dejsonize_tile(route.get(1)?)?,
+            ),
+            buyer: grid.dejsonize_tile(object.get("buyer")?)?,
+            item: items.named(&json_string(object.get("item")?)?)?,
+            amount: json_float(object.get("amount")?)?,
+            price: json_float(object.get("price")?)?,
+        })
+    }
+}
+
+/// Drop the trades made before `oldest` from `ledger`, which is kept oldest first
+pub fn forget_trades_before(ledger: &mut Vec<Trade>, oldest: u32) {
+    // the ledger is in year order, so the trades that have aged out are all at the front
+    let expired = ledger.partition_point(|trade| trade.year < oldest);
+    ledger.drain(..expired);
+}
+
+/// Everything a city has bought from and sold to one partner
+#[derive(Debug, Clone)]
+pub struct TradeTotals {
+    pub bought: Inventory,
+    pub sold: Inventory,
+    /// The coins paid for what was bought
+    pub spent: f32,
+    /// The coins got for what was sold
+    pub earned: f32,
+}
+
+impl TradeTotals {
+    #[must_use]
+    pub fn new(items: &Items) -> Self {
+        Self {
+            bought: Inventory::default(items),
+            sold: Inventory::default(items),
+            spent: 0.0,
+            earned: 0.0,
+        }
+    }
+
+    /// All the coins that changed hands, either way
+    #[must_use]
+    pub fn value(&self) -> f32 {
+        self.spent + self.earned
+    }
+}
+
+impl Jsonizable for TradeTotals {
+    fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
+        object! {
+            bought: self.bought.jsonize(config, items),
+            sold: self.sold.jsonize(config, items),
+            spent: self.spent,
+            earned: self.earned
+        }
+    }
+
+    fn dejsonize(src: &JsonValue, config: &Config, items: &Items) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        Some(Self {
+            bought: Inventory::dejsonize(object.get("bought")?, config, items)?,
+            sold: Inventory::dejsonize(object.get("sold")?, config, items)?,
+            spent: json_float(object.get("spent")?)?,
+            earned: json_float(object.get("earned")?)?,
+        })
+    }
+}
+
+/// A tile waiting to be visited, ordered so the cheapest comes out of the heap first
+#[derive(PartialEq)]
+struct Step {
+    cost: f32,
+    tile: usize,
+}
+
+impl Eq for Step {}
+
+impl Ord for Step {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.tile.cmp(&self.tile))
+    }
+}
+
+impl PartialOrd for Step {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The cheapest `kind` routes from the city at `start` to every city within `reach` of it
+///
+/// `cost` gives what it takes to cross each tile, or `None` where the route can't go. A route
+/// can end in a city on a tile it couldn't otherwise cross, which is how ships make port, but
+/// only coming from a tile it can cross: ships don't sail from one city straight into the next.
+pub fn find_routes(
+    grid: Grid,
+    start: usize,
+    kind: RouteKind,
+    cost: impl Fn(usize) -> Option<f32>,
+    is_city: impl Fn(usize) -> bool,
+    reach: f32,
+) -> Vec<(usize, TradeRoute)> {
+    // only the tiles the search gets to, so a search costs the same on any size of map
+    let mut best: HashMap<usize, (f32, usize)> = HashMap::from([(start, (0.0, start))]);
+    let mut heap = BinaryHeap::from([Step {
+        cost: 0.0,
+        tile: start,
+    }]);
+    let mut routes = Vec::new();
+    while let Some(Step { cost: so_far, tile }) = heap.pop() {
+        if best.get(&tile).is_some_and(|&(known, _)| known < so_far) {
+            continue;
+        }
+        if tile != start && is_city(tile) {
+            let mut path = vec![tile];
+            while let Some(&last) = path.last().filter(|&&last| last != start) {
+                path.push(best[&last].1);
+            }
+            path.reverse();
+            routes.push((
+                tile,
+                TradeRoute {
+                    kind,
+                    path,
+                    cost: so_far,
+                    trades: 0,
+                    strength: TradeRoute::OPENING_STRENGTH,
+                    carried: 0.0,
+                    value: 0.0,
+                },
+            ));
+        }
+        // roads run on through a city, but ships can't sail on from a port
+        if tile != start && cost(tile).is_none() {
+            continue;
+        }
+        let crossable = cost(tile).is_some();
+        for next in grid.neighbors(tile, 1) {
+            let port = crossable && is_city(next);
+            let Some(step) = cost(next).or_else(|| port.then_some(0.0)) else {
+                continue;
+            };
+            let total = step.mul_add(grid.distance(tile, next), so_far);
+            if total < reach && best.get(&next).is_none_or(|&(known, _)| total < known) {
+                best.insert(next, (total, tile));
+                heap.push(Step {
+                    cost: total,
+                    tile: next,
+                });
+            }
+        }
+    }
+    routes
+}
+
+/// The cheaper of the land and sea routes from the city at `start` to every city within `reach`
+/// of it, by the city at the other end
+///
+/// Each path runs from the first city of the pair to the second, the way routes are keyed.
+pub fn routes_from<'a>(
+    grid: Grid,
+    start: usize,
+    biome: impl Fn(usize) -> &'a Biome,
+    waters: &Waters,
+    is_city: impl Fn(usize) -> bool,
+    reach: f32,
+) -> BTreeMap<usize, TradeRoute> {
+    let mut cheapest: BTreeMap<usize, TradeRoute> = BTreeMap::new();
+    for kind in RouteKind::iter() {
+        let cost = |tile| kind.cost(biome(tile), waters.get(tile));
+        for (end, mut route) in find_routes(grid, start, kind, cost, &is_city, reach) {
+            if start > end {
+                route.path.reverse();
+            }
+            if cheapest
+                .get(&end)
+                .is_none_or(|known| route.cost < known.cost)
+            {
+                cheapest.insert(end, route);
+            }
+        }
+    }
+    cheapest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use json::{object, JsonValue};
+    use rand::SeedableRng;
+
+    use super::{find_routes, forget_trades_before, RouteChange, RouteKind, Trade, TradeRoute};
+    use crate::{
+        magic::MagicSystem,
+        mkv::MarkovCollection,
+        sim::{
+            handle_trade,
+            tests::{city, plants},
+            Biomes, City, Coord, Grid, Item, Region, TileShape, Topology, Waters,
+        },
+        Config, Jsonizable, SimRng, World,
+    };
+
+    /// A newly opened land route along `path`, which costs a step a tile
+    fn route(path: Vec<usize>) -> TradeRoute {
+        TradeRoute {
+            kind: RouteKind::Land,
+            cost: (path.len() - 1) as f32,
+            path,
+            trades: 0,
+            strength: TradeRoute::OPENING_STRENGTH,
+            carried: 0.0,
+            value: 0.0,
+        }
+    }
+
+    #[test]
+    fn routes_go_around_walls() {
+        let grid = Grid::new(5, 5, Topology::Flat, TileShape::Square);
+        // a wall down the middle with a gap at the bottom
+        let wall = |tile| {
+            let Coord { x, y } = grid.coord(tile);
+            x == 2 && y < 4
+        };
+        let cities = [0, 4];
+        let routes = find_routes(
+            grid,
+            0,
+            RouteKind::Land,
+            |tile| (!wall(tile)).then_some(1.0),
+            |tile| cities.contains(&tile),
+            20.0,
+        );
+        let [(4, route)] = &routes[..] else {
+            panic!("expected one route, got {routes:?}")
+        };
+        assert_eq!((route.path[0], route.path[route.path.len() - 1]), (0, 4));
+        assert!(route.path.contains(&grid.index(Coord::new(2, 4)).unwrap()));
+        assert!(route.cost > grid.distance(0, 4));
+        // and nothing beyond reach
+        let routes = find_routes(
+            grid,
+            0,
+            RouteKind::Land,
+            |_| Some(1.0),
+            |tile| tile == 4,
+            3.0,
+        );
+        assert!(routes.is_empty());
+    }
+
+    #[test]
+    fn ships_need_water_between_ports() {
+        // a port, two tiles of sea, then two ports side by side on land
+        let grid = Grid::new(5, 1, Topology::Flat, TileShape::Square);
+        let routes = find_routes(
+            grid,
+            3,
+            RouteKind::Sea,
+            |tile| (1..3).contains(&tile).then_some(1.0),
+            |tile| [0, 3, 4].contains(&tile),
+            20.0,
+        );
+        let [(0, route)] = &routes[..] else {
+            panic!("expected one route, got {routes:?}")
+        };
+        assert_eq!(route.path, vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn trade_history_replays_to_the_network() {
+        let markov = MarkovCollection::builtin();
+        let mut rng = SimRng::seed_from_u64(1);
+        let items = plants(&["Wheat"]);
+        let config = Config {
+            world_size: (8, 1),
+            ..Config::default()
+        };
+        let grid = config.grid();
+        // one plain over the whole map, with cities at either end and one in between
+        let plain = object! {
+            tiles: grid.indices().map(|tile| JsonValue::from(grid.coord(tile))).collect::<Vec<_>>(),
+            resources: {},
+            terrain: "Plain",
+            monster: null,
+        };
+        // the city at the far end has died out, but still has a route to the first
+        let mut ruin = city(7, &[], &[], &items).jsonize(&config, &items);
+        ruin["population"] = 0.into();
+        let mut world = World {
+            config,
+            current_year: 0,
+            seed: 1,
+            rng: rng.clone(),
+            region_map: vec![0; grid.len()],
+            region_list: vec![Region::dejsonize(&plain, &config, &items).unwrap()],
+            city_list: BTreeMap::from([
+                (0, city(0, &[], &[], &items)),
+                (3, city(3, &[], &[], &items)),
+                (7, City::dejsonize(&ruin, &config, &items).unwrap()),
+            ]),
+            trade_connections: HashMap::from([((0, 7), route((0..8).collect()))]),
+            trade_connections_list: vec![(0, 7)],
+            trade_history: Vec::new(),
+            trade_ledger: Vec::new(),
+            items,
+            biomes: Biomes::default(),
+            waters: Waters::new(grid.len()),
+            magic: MagicSystem::gen(&mut rng, &markov),
+            observers: Vec::new(),
+        };
+        let mut routes: HashSet<(usize, usize)> = world.trade_connections.keys().copied().collect();
+        // with nothing traded, the routes fade away and open again
+        for _ in 0..60 {
+            let populations: Vec<_> = world
+                .cities()
+                .map(|city| (city.pos(), city.population()))
+                .collect();
+            world.update_trade_network(&populations);
+            world.current_year += 1;
+        }
+        let changes: Vec<RouteChange> = world
+            .trade_history
+            .iter()
+            .map(|event| event.change)
+            .collect();
+        assert!(changes.contains(&RouteChange::Ruin));
+        assert!(changes.contains(&RouteChange::Isolation));
+        assert!(changes.contains(&RouteChange::Disuse));
+        for event in &world.trade_history {
+            if event.change.opens() {
+                assert!(routes.insert(event.route), "{event:?} was already open");
+            } else {
+                assert!(routes.remove(&event.route), "{event:?} wasn't open");
+            }
+        }
+        assert_eq!(routes, world.trade_connections.keys().copied().collect());
+        for (first, second) in routes {
+            assert!(world.is_living(first) && world.is_living(second));
+        }
+    }
+
+    #[test]
+    fn trade_ledger_adds_up() {
+        let items = plants(&["Wheat", "Rice"]);
+        let config = Config::default();
+        let mut city_list = BTreeMap::from([
+            (0, city(0, &[5.0, 2.0], &[0.0, 1000.0], &items)),
+            (1, city(1, &[1.0, 4.0], &[60.0, 0.0], &items)),
+        ]);
+        let mut trade_connections = HashMap::from([((0, 1), route(vec![0, 1]))]);
+        let mut ledger = Vec::new();
+        for year in 0..3 {
+            let (cities, routes) = (&mut city_list, &mut trade_connections);
+            let trades = handle_trade((0, 1), cities, routes, year, &config, &items, &[]);
+            ledger.extend(trades);
+        }
+        // every city's totals with its partner are what the ledger says went between them
+        for (buyer, seller) in [(0, 1), (1, 0)] {
+            let bought: Vec<&Trade> = ledger.iter().filter(|trade| trade.buyer == buyer).collect();
+            assert!(!bought.is_empty());
+            assert!(bought.iter().all(|trade| trade.seller() == seller));
+            let spent: f32 = bought.iter().map(|trade| trade.value()).sum();
+            let close = |total: f32| (total - spent).abs() <= spent * 1e-4;
+            assert!(close(city_list[&buyer].partners()[&seller].spent));
+            assert!(close(city_list[&seller].partners()[&buyer].earned));
+        }
+        // and the ledger survives a save
+        let saved: Vec<Trade> = ledger
+            .iter()
+            .map(|trade| Trade::dejsonize(&trade.jsonize(&config, &items), &config, &items))
+            .collect::<Option<_>>()
+            .unwrap();
+        assert_eq!(saved, ledger);
+    }
+
+    #[test]
+    fn trade_ledger_forgets_old_years() {
+        let items = plants(&[]);
+        let fish = items.index(Item::Fish).unwrap();
+        let mut ledger: Vec<Trade> = [0, 0, 1, 2, 2, 3]
+            .into_iter()
+            .map(|year| Trade {
+                year,
+                route: (0, 1),
+                buyer: 0,
+                item: fish,
+                amount: 1.0,
+                price: 1.0,
+            })
+            .collect();
+        let years = |ledger: &[Trade]| ledger.iter().map(|trade| trade.year).collect::<Vec<_>>();
+        forget_trades_before(&mut ledger, 0);
+        assert_eq!(years(&ledger), [0, 0, 1, 2, 2, 3]);
+        forget_trades_before(&mut ledger, 2);
+        assert_eq!(years(&ledger), [2, 2, 3]);
+        forget_trades_before(&mut ledger, 10);
+        assert!(ledger.is_empty());
+    }
+}
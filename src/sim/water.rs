@@ -0,0 +1,234 @@
+use json::{object, JsonValue};
+use rand::Rng;
+use strum_macros::AsRefStr;
+
+use crate::{jsonize::Jsonizable, Config, Items};
+
+use super::{Climate, Grid};
+
+/// Fresh water on a land tile
+#[derive(Debug, Clone, Copy, AsRefStr, PartialEq, Eq)]
+pub enum Water {
+    River,
+    Lake,
+}
+
+impl Water {
+    #[must_use]
+    pub const fn color(self) -> [u8; 3] {
+        match self {
+            Self::River => [60, 110, 200],
+            Self::Lake => [50, 90, 170],
+        }
+    }
+}
+
+/// The rivers and lakes of a world, tile by tile
+///
+/// Water tiles that touch each other make up a basin. Cities next to the same basin can ship
+/// goods along it.
+#[derive(Debug, Clone)]
+pub struct Waters {
+    tiles: Vec<Option<Water>>,
+    basins: Vec<Option<usize>>,
+}
+
+impl Waters {
+    /// Shipping along a river is cheap, so trade crosses river and lake tiles this many times
+    /// more easily
+    pub const TRADE_BONUS: f32 = 1.5;
+
+    /// A map of `tiles` tiles with no water on it
+    #[must_use]
+    pub fn new(tiles: usize) -> Self {
+        Self {
+            tiles: vec![None; tiles],
+            basins: vec![None; tiles],
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, tile: usize) -> Option<Water> {
+        self.tiles.get(tile).copied().flatten()
+    }
+
+    /// Put water on `tile`. Call [`Waters::find_basins`] once everything is in place.
+    pub fn set(&mut self, tile: usize, water: Water) {
+        self.tiles[tile] = Some(water);
+    }
+
+    /// Lakes in rainy hollows, and rivers that run downhill from rainy high ground until they
+    /// reach water, leaving a lake if they get stuck on the way. `climates` and `passable` give
+    /// each tile's climate and whether it's land.
+    pub fn gen<R: Rng + ?Sized>(
+        rng: &mut R,
+        grid: Grid,
+        climates: &[Climate],
+        passable: &[bool],
+    ) -> Self {
+        let mut waters = Self::new(grid.len());
+        for tile in grid.indices() {
+            let elevation = climates[tile].elevation;
+            if passable[tile]
+                && climates[tile].rainfall > 0.5
+                && grid
+                    .neighbors(tile, 1)
+                    .iter()
+                    .all(|&neighbor| climates[neighbor].elevation > elevation)
+            {
+                waters.set(tile, Water::Lake);
+            }
+        }
+        for source in grid.indices() {
+            let climate = climates[source];
+            if !passable[source]
+                || climate.elevation < 0.7
+                || rng.gen::<f32>() > climate.rainfall * 0.05
+            {
+                continue;
+            }
+            let mut tile = source;
+            // every step goes downhill, so this can't loop forever
+            while passable[tile] && waters.get(tile).is_none() {
+                let lowest = grid
+                    .neighbors(tile, 0)
+                    .into_iter()
+                    .min_by(|&a, &b| climates[a].elevation.total_cmp(&climates[b].elevation));
+                match lowest {
+                    Some(next) if climates[next].elevation < climates[tile].elevation => {
+                        waters.set(tile, Water::River);
+                        tile = next;
+                    }
+                    _ => {
+                        waters.set(tile, Water::Lake);
+                        break;
+                    }
+                }
+            }
+        }
+        waters.find_basins(grid);
+        waters
+    }
+
+    /// Group the water tiles into basins
+    pub fn find_basins(&mut self, grid: Grid) {
+        self.basins = vec![None; self.tiles.len()];
+        let mut basins = 0;
+        for start in grid.indices() {
+            if self.get(start).is_none() || self.basins[start].is_some() {
+                continue;
+            }
+            self.basins[start] = Some(basins);
+            let mut frontier = vec![start];
+            while let Some(tile) = frontier.pop() {
+                for neighbor in grid.neighbors(tile, 1) {
+                    if self.get(neighbor).is_some() && self.basins[neighbor].is_none() {
+                        self.basins[neighbor] = Some(basins);
+                        frontier.push(neighbor);
+                    }
+                }
+            }
+            basins += 1;
+        }
+    }
+
+    /// The basins on or next to `tile`
+    fn basins_near(&self, grid: Grid, tile: usize) -> Vec<usize> {
+        let mut basins: Vec<usize> = std::iter::once(tile)
+            .chain(grid.neighbors(tile, 1))
+            .filter_map(|tile| self.basins.get(tile).copied().flatten())
+            .collect();
+        basins.sort_unstable();
+        basins.dedup();
+        basins
+    }
+
+    /// Whether there's a river or lake on or next to `tile`
+    #[must_use]
+    pub fn is_near(&self, grid: Grid, tile: usize) -> bool {
+        !self.basins_near(grid, tile).is_empty()
+    }
+}
+
+impl Jsonizable for Waters {
+    fn jsonize(&self, config: &Config, _items: &Items) -> JsonValue {
+        let grid = config.grid();
+        let tiles_of = |water: Water| {
+            grid.indices()
+                .filter(|&tile| self.get(tile) == Some(water))
+                .map(|tile| JsonValue::from(grid.coord(tile)))
+                .collect::<Vec<_>>()
+        };
+        object! {
+            River: tiles_of(Water::River),
+            Lake: tiles_of(Water::Lake)
+        }
+    }
+
+    fn dejsonize(src: &JsonValue, config: &Config, _items: &Items) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        let grid = config.grid();
+        let mut waters = Self::new(grid.len());
+        for water in [Water::River, Water::Lake] {
+            let Some(JsonValue::Array(tiles)) = object.get(water.as_ref()) else { return None };
+            for tile in tiles {
+                waters.set(grid.dejsonize_tile(tile)?, water);
+            }
+        }
+        waters.find_basins(grid);
+        Some(waters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::{Water, Waters};
+    use crate::sim::{Climate, Grid, TileShape, Topology};
+
+    #[test]
+    fn basins_join_touching_water() {
+        // 0  1  2  3  4  5
+        // 6  7  8  9  10 11
+        // 12 13 14 15 16 17
+        let grid = Grid::new(6, 3, Topology::Flat, TileShape::Square);
+        let mut waters = Waters::new(grid.len());
+        waters.set(0, Water::River);
+        waters.set(7, Water::River);
+        waters.set(5, Water::Lake);
+        waters.set(11, Water::Lake);
+        waters.find_basins(grid);
+        assert!(waters.basins[0].is_some());
+        assert_eq!(waters.basins[0], waters.basins[7]);
+        assert_eq!(waters.basins[5], waters.basins[11]);
+        assert_ne!(waters.basins[0], waters.basins[5]);
+        assert_eq!(waters.basins[1], None);
+        assert!(waters.is_near(grid, 0));
+        assert!(waters.is_near(grid, 14));
+        assert!(waters.is_near(grid, 16));
+        assert!(!waters.is_near(grid, 3));
+        assert!(!waters.is_near(grid, 15));
+    }
+
+    #[test]
+    fn rivers_run_downhill_into_water() {
+        // a peak, a hollow, a ridge, the sea and a flat stretch of land
+        let grid = Grid::new(7, 1, Topology::Flat, TileShape::Square);
+        let climates: Vec<Climate> = [0.9, 0.3, 0.5, 0.8, 0.1, 0.6, 0.6]
+            .into_iter()
+            .map(|elevation| Climate::new(elevation, 0.5, 1.0))
+            .collect();
+        let passable = [true, true, true, true, false, true, true];
+        // every high tile starts a river
+        let waters = Waters::gen(&mut StepRng::new(0, 0), grid, &climates, &passable);
+        let tiles: Vec<_> = grid.indices().map(|tile| waters.get(tile)).collect();
+        let (river, lake) = (Some(Water::River), Some(Water::Lake));
+        assert_eq!(tiles, [river, lake, None, river, None, None, None]);
+        // the peak's river runs into the hollow's lake, the ridge's into the sea
+        assert_eq!(waters.basins[0], waters.basins[1]);
+        assert_ne!(waters.basins[0], waters.basins[3]);
+        assert!(waters.is_near(grid, 2));
+        assert!(!waters.is_near(grid, 5));
+    }
+}
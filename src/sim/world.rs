@@ -1,96 +1,411 @@
+use std::{collections::BTreeMap, ops::Index};
+
 use json::{object, JsonValue};
-use rand::{prelude::Distribution, rngs::ThreadRng, seq::SliceRandom, Rng};
-use strum::IntoEnumIterator;
+use rand::{prelude::Distribution, seq::SliceRandom, Rng};
 use strum_macros::{AsRefStr, EnumIter};
 
 use crate::{
-    jsonize::{json_array_to_usize, json_int, json_string, Jsonizable, SuperJsonizable},
+    jsonize::{json_float, json_int, json_string, Jsonizable, SuperJsonizable},
     mkv::MarkovData,
-    sim::{get_adj, usize_to_vec, Item},
+    sim::Item,
     Config, Items,
 };
 
-use super::Inventory;
+use super::{Grid, Inventory};
 
 #[derive(Debug, Clone, Copy, AsRefStr, PartialEq, Eq, EnumIter)]
-pub enum Terrain {
-    Ocean,
-    Plain,
-    Forest,
-    Mountain,
-    Desert,
-    Jungle,
+pub enum Species {
+    Leviathan,
+    Dragon,
+    Beast,
+    Worm,
 }
 
-impl Terrain {
-    pub fn monster_types(self) -> Vec<Species> {
-        match self {
-            Self::Ocean => vec![Species::Leviathan],
-            Self::Plain => vec![Species::Dragon, Species::Beast],
-            Self::Forest => vec![Species::Beast],
-            Self::Mountain => vec![Species::Dragon],
-            Self::Desert => vec![Species::Worm, Species::Dragon],
-            Self::Jungle => vec![Species::Beast, Species::Worm],
+impl SuperJsonizable for Species {
+    fn s_jsonize(&self) -> JsonValue {
+        JsonValue::from(self.as_ref())
+    }
+
+    fn s_dejsonize(src: &JsonValue) -> Option<Self> {
+        match json_string(src)?.as_ref() {
+            "Leviathan" => Some(Self::Leviathan),
+            "Dragon" => Some(Self::Dragon),
+            "Beast" => Some(Self::Beast),
+            "Worm" => Some(Self::Worm),
+            _ => None,
         }
     }
+}
 
-    pub fn color(&self) -> Vec<u8> {
-        match &self {
-            Self::Ocean => vec![70, 90, 140],
-            Self::Plain => vec![120, 140, 80],
-            Self::Forest => vec![90, 150, 80],
-            Self::Mountain => vec![96, 96, 96],
-            Self::Desert => vec![160, 140, 90],
-            Self::Jungle => vec![40, 130, 80],
+/// The lay of the land, each part from 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Climate {
+    pub elevation: f32,
+    pub temperature: f32,
+    pub rainfall: f32,
+}
+
+impl Climate {
+    /// Land below this elevation is under water
+    pub const SEA_LEVEL: f32 = 0.4;
+
+    #[must_use]
+    pub const fn new(elevation: f32, temperature: f32, rainfall: f32) -> Self {
+        Self {
+            elevation,
+            temperature,
+            rainfall,
+        }
+    }
+
+    #[must_use]
+    pub fn is_water(self) -> bool {
+        self.elevation < Self::SEA_LEVEL
+    }
+
+    /// How different two climates are
+    #[must_use]
+    pub fn distance(self, other: Self) -> f32 {
+        let elevation = self.elevation - other.elevation;
+        let temperature = self.temperature - other.temperature;
+        let rainfall = self.rainfall - other.rainfall;
+        elevation.hypot(temperature).hypot(rainfall)
+    }
+
+    /// The average climate of `climates`, or the default if there are none
+    pub fn mean(climates: impl Iterator<Item = Self>) -> Self {
+        let (sum, count) = climates.fold((Self::new(0.0, 0.0, 0.0), 0), |(sum, count), c| {
+            (
+                Self::new(
+                    sum.elevation + c.elevation,
+                    sum.temperature + c.temperature,
+                    sum.rainfall + c.rainfall,
+                ),
+                count + 1,
+            )
+        });
+        if count == 0 {
+            return Self::default();
         }
+        let count = count as f32;
+        Self::new(
+            sum.elevation / count,
+            sum.temperature / count,
+            sum.rainfall / count,
+        )
+    }
+
+    fn jsonize(self) -> JsonValue {
+        object! {
+            Elevation: self.elevation,
+            Temperature: self.temperature,
+            Rainfall: self.rainfall
+        }
+    }
+
+    fn dejsonize(src: &JsonValue) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        Some(Self::new(
+            json_float(object.get("Elevation")?)?,
+            json_float(object.get("Temperature")?)?,
+            json_float(object.get("Rainfall")?)?,
+        ))
     }
 }
 
-impl Jsonizable for Terrain {
-    fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
+/// Middling everything, just above sea level
+impl Default for Climate {
+    fn default() -> Self {
+        Self::new(0.5, 0.5, 0.5)
+    }
+}
+
+/// A kind of terrain, and what its regions are like
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    /// The chance of each kind of metal showing up in a region, which also scales how much
+    /// there is. The same goes for the other resources, except fish: every region has some
+    /// fish, and this adds to it.
+    pub metal: f32,
+    pub gem: f32,
+    pub plant: f32,
+    pub animal: f32,
+    pub fish: f32,
+    /// The monsters that can lair here. With none, regions of this terrain have no monster.
+    pub monsters: Vec<Species>,
+    pub color: [u8; 3],
+    /// The character the terminal map draws it with
+    pub glyph: char,
+    /// Whether people can walk across it. Impassable biomes are water: land next to them is
+    /// coast, and travelers go around them.
+    pub passable: bool,
+    /// Scales the chance of founding a city here. Cities are never founded on impassable tiles.
+    pub city_suitability: f32,
+    /// How hard a tile of it is for trade to cross, by land or, if it's impassable, by sea
+    pub travel_cost: f32,
+    /// The climate this biome is most at home in. Regions get the closest biome to their own
+    /// climate, out of the impassable biomes for water and the passable ones for land.
+    pub climate: Climate,
+}
+
+impl Biome {
+    /// A plain land biome with nothing in it, drawn with the first letter of `name`
+    fn new(name: &str) -> Self {
+        Self {
+            metal: 0.0,
+            gem: 0.0,
+            plant: 0.0,
+            animal: 0.0,
+            fish: 0.0,
+            monsters: Vec::new(),
+            color: [128, 128, 128],
+            glyph: name.chars().next().unwrap_or('?'),
+            passable: true,
+            city_suitability: 1.0,
+            travel_cost: 1.0,
+            climate: Climate::default(),
+        }
+    }
+
+    /// The built-in biome called `name`, used when a gen file doesn't define it
+    #[must_use]
+    pub fn fallback(name: &str) -> Option<Self> {
+        let (metal, gem, plant, animal, glyph) = match name {
+            "Ocean" => (0.0, 0.0, 0.0, 0.0, '~'),
+            "Plain" => (0.2, 0.1, 0.4, 0.9, '%'),
+            "Forest" => (0.1, 0.2, 0.9, 0.4, '♧'),
+            "Mountain" => (0.9, 0.4, 0.2, 0.1, '◮'),
+            "Desert" => (0.4, 0.9, 0.1, 0.2, '#'),
+            "Jungle" => (0.1, 0.4, 0.9, 0.2, '♤'),
+            _ => return None,
+        };
+        let (monsters, color) = match name {
+            "Ocean" => (vec![Species::Leviathan], [70, 90, 140]),
+            "Plain" => (vec![Species::Dragon, Species::Beast], [120, 140, 80]),
+            "Forest" => (vec![Species::Beast], [90, 150, 80]),
+            "Mountain" => (vec![Species::Dragon], [96, 96, 96]),
+            "Desert" => (vec![Species::Worm, Species::Dragon], [160, 140, 90]),
+            _ => (vec![Species::Beast, Species::Worm], [40, 130, 80]),
+        };
+        let travel_cost = match name {
+            "Ocean" => 0.5,
+            "Plain" => 1.0,
+            "Forest" => 1.5,
+            "Mountain" => 4.0,
+            "Desert" => 2.0,
+            _ => 2.5,
+        };
+        let (elevation, temperature, rainfall) = match name {
+            "Ocean" => (0.1, 0.5, 0.5),
+            "Plain" => (0.5, 0.5, 0.35),
+            "Forest" => (0.55, 0.45, 0.65),
+            "Mountain" => (1.0, 0.3, 0.5),
+            "Desert" => (0.5, 0.8, 0.1),
+            _ => (0.5, 0.85, 0.9),
+        };
+        let land = name != Biomes::OCEAN;
+        Some(Self {
+            metal,
+            gem,
+            plant,
+            animal,
+            fish: 0.0,
+            monsters,
+            color,
+            glyph,
+            passable: land,
+            city_suitability: if land { 1.0 } else { 0.0 },
+            travel_cost,
+            climate: Climate::new(elevation, temperature, rainfall),
+        })
+    }
+
+    /// The color as a CSS `rgb()` value
+    #[must_use]
+    pub fn css_color(&self) -> String {
+        let [r, g, b] = self.color;
+        format!("rgb({r}, {g}, {b})")
+    }
+
+    fn jsonize(&self) -> JsonValue {
         object! {
             Resources: {
-                Animal: 0.3, Fish: 0.0, Plant: 0.9, Metal: 0.1, Gemstone: 0.1
+                Animal: self.animal,
+                Fish: self.fish,
+                Plant: self.plant,
+                Metal: self.metal,
+                Gemstone: self.gem
             },
-            Monsters: self.monster_types().jsonize(config, items),
-            Color: self.color()
+            Monsters: self.monsters.iter().map(SuperJsonizable::s_jsonize).collect::<Vec<_>>(),
+            Color: self.color.to_vec(),
+            Glyph: self.glyph.to_string(),
+            Passable: self.passable,
+            CitySuitability: self.city_suitability,
+            TravelCost: self.travel_cost,
+            Climate: self.climate.jsonize()
         }
     }
 
-    fn dejsonize(src: &JsonValue, _config: &Config, _items: &Items) -> Option<Self> {
-        match json_string(src)?.as_ref() {
-            "Ocean" => Some(Self::Ocean),
-            "Plain" => Some(Self::Plain),
-            "Forest" => Some(Self::Forest),
-            "Mountain" => Some(Self::Mountain),
-            "Desert" => Some(Self::Desert),
-            "Jungle" => Some(Self::Jungle),
-            _ => None,
-        }
+    /// Read a biome, keeping `base`'s glyph, passability, city suitability, travel cost and
+    /// climate where the file doesn't set them. Older gen files only have resources, monsters and a color.
+    fn dejsonize(src: &JsonValue, base: &Self) -> Option<Self> {
+        let JsonValue::Object(object) = src else { return None };
+        let JsonValue::Object(resources) = object.get("Resources")? else { return None };
+        let Some(JsonValue::Array(monsters)) = object.get("Monsters") else { return None };
+        let Some(JsonValue::Array(color)) = object.get("Color") else { return None };
+        // a resource the biome doesn't list never shows up
+        let weight = |name| resources.get(name).and_then(json_float).unwrap_or_default();
+        Some(Self {
+            metal: weight("Metal"),
+            gem: weight("Gemstone"),
+            plant: weight("Plant"),
+            animal: weight("Animal"),
+            fish: weight("Fish"),
+            monsters: {
+                let mut species = Vec::new();
+                for monster in monsters {
+                    species.push(Species::s_dejsonize(monster)?);
+                }
+                species
+            },
+            color: [
+                json_int(color.first()?)?.try_into().ok()?,
+                json_int(color.get(1)?)?.try_into().ok()?,
+                json_int(color.get(2)?)?.try_into().ok()?,
+            ],
+            glyph: match object.get("Glyph") {
+                None => base.glyph,
+                Some(glyph) => json_string(glyph)?.chars().next()?,
+            },
+            passable: match object.get("Passable") {
+                None => base.passable,
+                Some(passable) => passable.as_bool()?,
+            },
+            city_suitability: match object.get("CitySuitability") {
+                None => base.city_suitability,
+                Some(suitability) => json_float(suitability)?,
+            },
+            travel_cost: match object.get("TravelCost") {
+                None => base.travel_cost,
+                Some(cost) => json_float(cost)?,
+            },
+            climate: match object.get("Climate") {
+                None => base.climate,
+                Some(climate) => Climate::dejsonize(climate)?,
+            },
+        })
     }
 }
 
-#[derive(Debug, Clone, Copy, AsRefStr, PartialEq, Eq, EnumIter)]
-pub enum Species {
-    Leviathan,
-    Dragon,
-    Beast,
-    Worm,
+/// Every biome in a world, by name. Regions refer to their biome by name, and there's always
+/// a [`Biomes::OCEAN`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biomes(BTreeMap<String, Biome>);
+
+impl Biomes {
+    /// The biome of the ocean region, which rings flat maps
+    pub const OCEAN: &'static str = "Ocean";
+
+    /// The biome called `terrain`, if there is one
+    #[must_use]
+    pub fn get(&self, terrain: &str) -> Option<&Biome> {
+        self.0.get(terrain)
+    }
+
+    /// The name of the biome closest to `climate`, out of the water biomes if it's under water
+    /// and the land ones otherwise. If there aren't any of the right kind, any biome will do.
+    #[must_use]
+    pub fn closest(&self, climate: Climate) -> &str {
+        let closest = |water_only: Option<bool>| {
+            self.0
+                .iter()
+                .filter(|(_, biome)| water_only.is_none_or(|water| biome.passable != water))
+                .min_by(|(_, a), (_, b)| {
+                    climate
+                        .distance(a.climate)
+                        .total_cmp(&climate.distance(b.climate))
+                })
+                .map(|(name, _)| name.as_str())
+        };
+        closest(Some(climate.is_water()))
+            .or_else(|| closest(None))
+            .unwrap_or(Self::OCEAN)
+    }
 }
 
-impl SuperJsonizable for Species {
+/// Loading a world fails if a region's terrain isn't one of its biomes, so a region's terrain
+/// can always be looked up this way.
+impl Index<&str> for Biomes {
+    type Output = Biome;
+
+    fn index(&self, terrain: &str) -> &Biome {
+        self.get(terrain)
+            .unwrap_or_else(|| panic!("there's no biome called {terrain}"))
+    }
+}
+
+impl Default for Biomes {
+    fn default() -> Self {
+        Self(
+            ["Ocean", "Plain", "Forest", "Mountain", "Desert", "Jungle"]
+                .into_iter()
+                .filter_map(|name| Some((String::from(name), Biome::fallback(name)?)))
+                .collect(),
+        )
+    }
+}
+
+impl SuperJsonizable for Biomes {
     fn s_jsonize(&self) -> JsonValue {
-        JsonValue::from(self.as_ref())
+        let mut object = JsonValue::new_object();
+        for (name, biome) in &self.0 {
+            object[name.as_str()] = biome.jsonize();
+        }
+        object
     }
 
+    /// The block replaces the built-in biomes, except that an ocean is added if it's missing
     fn s_dejsonize(src: &JsonValue) -> Option<Self> {
-        match json_string(src)?.as_ref() {
-            "Leviathan" => Some(Self::Leviathan),
-            "Dragon" => Some(Self::Dragon),
-            "Beast" => Some(Self::Beast),
-            "Worm" => Some(Self::Worm),
-            _ => None,
+        let JsonValue::Object(object) = src else { return None };
+        let mut biomes = BTreeMap::new();
+        for (name, biome) in object.iter() {
+            let base = Biome::fallback(name).unwrap_or_else(|| Biome::new(name));
+            biomes.insert(String::from(name), Biome::dejsonize(biome, &base)?);
+        }
+        if !biomes.contains_key(Self::OCEAN) {
+            biomes.insert(String::from(Self::OCEAN), Biome::fallback(Self::OCEAN)?);
         }
+        Some(Self(biomes))
+    }
+}
+
+/// Where two regions meet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Border {
+    /// How many tile edges the two regions share
+    pub length: usize,
+    /// Whether one side is land and the other water
+    pub coastal: bool,
+}
+
+impl Border {
+    fn jsonize(self, region: usize) -> JsonValue {
+        object! {
+            region: region,
+            length: self.length,
+            coastal: self.coastal
+        }
+    }
+
+    fn dejsonize(src: &JsonValue) -> Option<(usize, Self)> {
+        let JsonValue::Object(object) = src else { return None };
+        Some((
+            json_int(object.get("region")?)? as usize,
+            Self {
+                length: json_int(object.get("length")?)? as usize,
+                coastal: object.get("coastal")?.as_bool()?,
+            },
+        ))
     }
 }
 
@@ -99,44 +414,119 @@ pub struct Region {
     id: usize,
     tiles: Vec<usize>,
     resources: Inventory,
-    terrain: Terrain,
-    adjacent_regions: Vec<usize>,
+    terrain: String,
+    climate: Climate,
+    borders: BTreeMap<usize, Border>,
     monster: Option<Monster>,
 }
 
 impl Region {
+    #[must_use]
     pub const fn id(&self) -> usize {
         self.id
     }
 
-    pub fn set_id(&mut self, id: usize) {
-        self.id = id
+    pub const fn set_id(&mut self, id: usize) {
+        self.id = id;
     }
 
+    #[must_use]
     pub const fn tiles(&self) -> &Vec<usize> {
         &self.tiles
     }
 
+    #[must_use]
     pub const fn resources(&self) -> &Inventory {
         &self.resources
     }
 
-    pub const fn terrain(&self) -> Terrain {
-        self.terrain
+    /// The name of the region's biome
+    #[must_use]
+    pub fn terrain(&self) -> &str {
+        &self.terrain
+    }
+
+    pub fn set_terrain(&mut self, terrain: &str) {
+        self.terrain = String::from(terrain);
     }
 
-    pub fn set_terrain(&mut self, terrain: Terrain) {
-        self.terrain = terrain;
+    /// The climate across the region
+    #[must_use]
+    pub const fn climate(&self) -> Climate {
+        self.climate
+    }
+
+    /// The regions this one borders, by id
+    #[must_use]
+    pub const fn borders(&self) -> &BTreeMap<usize, Border> {
+        &self.borders
+    }
+
+    #[must_use]
+    pub fn border(&self, region: usize) -> Option<Border> {
+        self.borders.get(&region).copied()
+    }
+
+    /// Whether the region is on a coast, from either side
+    #[must_use]
+    pub fn is_coastal(&self) -> bool {
+        self.borders.values().any(|border| border.coastal)
+    }
+
+    /// The tile where the region's monster lairs, if it has one that's still alive
+    #[must_use]
+    pub fn lair(&self) -> Option<usize> {
+        self.monster
+            .as_ref()
+            .filter(|monster| monster.alive)
+            .map(|monster| monster.location)
+    }
+
+    /// Work out where `regions` meet from the tiles they cover. Regions are indexed by id.
+    pub fn find_borders(regions: &mut [Self], grid: Grid, biomes: &Biomes) {
+        let mut region_map = vec![0; grid.len()];
+        for region in regions.iter() {
+            for &tile in &region.tiles {
+                region_map[tile] = region.id;
+            }
+        }
+        let water: Vec<bool> = regions
+            .iter()
+            .map(|region| !biomes[&region.terrain].passable)
+            .collect();
+        for region in regions.iter_mut() {
+            region.borders.clear();
+            for &tile in &region.tiles {
+                for neighbor in grid.neighbors(tile, 0) {
+                    let other = region_map[neighbor];
+                    if other != region.id {
+                        region
+                            .borders
+                            .entry(other)
+                            .or_insert(Border {
+                                length: 0,
+                                coastal: water[region.id] != water[other],
+                            })
+                            .length += 1;
+                    }
+                }
+            }
+        }
     }
 }
 
 impl Jsonizable for Region {
     fn jsonize(&self, config: &Config, items: &Items) -> JsonValue {
         object! {
-            tiles: self.tiles.iter().map(|&tile| usize_to_vec(tile, config)).collect::<Vec<Vec<usize>>>(),
+            tiles: self.tiles.iter().map(|&tile| JsonValue::from(config.grid().coord(tile))).collect::<Vec<_>>(),
             resources: self.resources.jsonize(config, items),
-            terrain: self.terrain.as_ref(),
-            adjacent_regions: self.adjacent_regions.clone(),
+            terrain: self.terrain.clone(),
+            elevation: self.climate.elevation,
+            temperature: self.climate.temperature,
+            rainfall: self.climate.rainfall,
+            // kept alongside the borders for the python tools
+            adjacent_regions: self.borders.keys().copied().collect::<Vec<_>>(),
+            borders: self.borders.iter().map(|(&region, border)| border.jsonize(region)).collect::<Vec<_>>(),
             ancestor_race: "Human",
             demographics: object!{Human: 1.0},
             monster: self.monster.clone().map(|m| m.jsonize(config, items))
@@ -147,24 +537,34 @@ impl Jsonizable for Region {
         // println!("dj region");
         let JsonValue::Object(object) = src else { return None };
         let Some(JsonValue::Array(tiles_array)) = object.get("tiles") else { return None };
-        let Some(JsonValue::Array(adj_array)) = object.get("adjacent_regions") else { return None };
         Some(Self {
             id: 0,
             tiles: {
                 let mut tiles = Vec::new();
                 for tile in tiles_array {
-                    tiles.push(json_array_to_usize(tile, config)?);
+                    tiles.push(config.grid().dejsonize_tile(tile)?);
                 }
                 tiles
             },
             resources: Inventory::dejsonize(object.get("resources")?, config, items)?,
-            terrain: Terrain::dejsonize(object.get("terrain")?, config, items)?,
-            adjacent_regions: {
-                let mut regions = Vec::new();
-                for item in adj_array {
-                    regions.push(json_int(item)? as usize);
-                }
-                regions
+            terrain: json_string(object.get("terrain")?)?,
+            // saves from before climates were generated get a middling one
+            climate: {
+                let field = |name| object.get(name).and_then(json_float);
+                let default = Climate::default();
+                Climate::new(
+                    field("elevation").unwrap_or(default.elevation),
+                    field("temperature").unwrap_or(default.temperature),
+                    field("rainfall").unwrap_or(default.rainfall),
+                )
+            },
+            // saves from before borders were stored get them worked out again on load
+            borders: match object.get("borders") {
+                Some(JsonValue::Array(borders)) => borders
+                    .iter()
+                    .map(Border::dejsonize)
+                    .collect::<Option<_>>()?,
+                _ => BTreeMap::new(),
             },
             monster: Monster::dejsonize(object.get("monster")?, config, items),
         })
@@ -172,78 +572,51 @@ impl Jsonizable for Region {
 }
 
 impl Region {
-    pub fn gen(
+    pub fn gen<R: Rng + ?Sized>(
         id: usize,
-        region_map: &[usize],
-        rng: &mut ThreadRng,
-        region_count: usize,
+        tiles: Vec<usize>,
+        rng: &mut R,
         markov_data_monster: &MarkovData,
-        config: &Config,
         items: &Items,
+        biomes: &Biomes,
+        climate: Climate,
     ) -> Self {
-        let tiles: Vec<usize> = (0..(config.world_size.0 * config.world_size.1))
-            .filter(|&i| region_map[i] == id)
-            .collect();
-        let terrain = {
-            let ter_iter = Terrain::iter().collect::<Vec<_>>();
-            let ter = ter_iter.choose(rng);
-            match ter {
-                Some(&terrain) => terrain,
-                None => Terrain::Ocean,
-            }
-        };
+        let terrain = String::from(biomes.closest(climate));
+        let biome = &biomes[&terrain];
         let resources = {
-            let (metal, gem, plant, animal) = match terrain {
-                Terrain::Plain => (0.2, 0.1, 0.4, 0.9),
-                Terrain::Forest => (0.1, 0.2, 0.9, 0.4),
-                Terrain::Mountain => (0.9, 0.4, 0.2, 0.1),
-                Terrain::Desert => (0.4, 0.9, 0.1, 0.2),
-                Terrain::Jungle => (0.1, 0.4, 0.9, 0.2),
-                Terrain::Ocean => (0.0, 0.0, 0.0, 0.0),
-            };
-
             let mut resources = Inventory::default(items);
 
             macro_rules! run_type {
                 ($resource : expr, $resource_item : expr, $resource_names : expr) => {
                     for resource_type in 0..$resource_names.len() {
                         if rng.gen::<f32>() < $resource {
-                            resources.set(
-                                $resource_item(resource_type as u8).to_index(items).unwrap(),
-                                rng.gen::<f32>().mul_add($resource, 1.0),
-                            );
+                            let Some(index) = items.index($resource_item(resource_type as u8))
+                            else {
+                                continue;
+                            };
+                            resources.set(index, rng.gen::<f32>().mul_add($resource, 1.0));
                         }
                     }
                 };
             }
-            run_type!(metal, Item::Metal, items.metals);
-            run_type!(gem, Item::Gem, items.gems);
-            run_type!(plant, Item::Plant, items.plants);
-            run_type!(animal, Item::WildAnimal, items.animals);
-            resources.set(0, rng.gen::<f32>() * 2.0);
+            run_type!(biome.metal, Item::Metal, items.metals);
+            run_type!(biome.gem, Item::Gem, items.gems);
+            run_type!(biome.plant, Item::Plant, items.plants);
+            run_type!(biome.animal, Item::WildAnimal, items.animals);
+            if let Some(fish) = items.index(Item::Fish) {
+                resources.set(fish, rng.gen::<f32>() * 2.0 * (1.0 + biome.fish));
+            }
             resources
         };
+        let monster = Monster::gen(rng, &biome.monsters, &tiles, items, markov_data_monster);
         Self {
             id,
-            tiles: tiles.clone(),
+            tiles,
             resources,
             terrain,
-            adjacent_regions: (0..region_count)
-                .filter(|&neighbor_region| {
-                    tiles.iter().any(|&tile| {
-                        get_adj(tile, 1, config)
-                            .iter()
-                            .any(|&local_region| local_region == neighbor_region)
-                    })
-                })
-                .collect(),
-            monster: Some(Monster::gen(
-                rng,
-                terrain,
-                &tiles,
-                items,
-                markov_data_monster,
-            )),
+            climate,
+            borders: BTreeMap::new(),
+            monster,
         }
     }
 }
@@ -266,7 +639,7 @@ impl Jsonizable for Monster {
             desc: self.desc.clone(),
             inventory: self.inventory.jsonize(config, items),
             alive: self.alive,
-            location: usize_to_vec(self.location, config)
+            location: config.grid().coord(self.location)
         }
     }
 
@@ -275,7 +648,7 @@ impl Jsonizable for Monster {
         let Some(JsonValue::Boolean(alive)) = object.get("alive") else { return None };
         Some(Self {
             alive: *alive,
-            location: json_array_to_usize(object.get("location")?, config)?,
+            location: config.grid().dejsonize_tile(object.get("location")?)?,
             inventory: Inventory::dejsonize(object.get("inventory")?, config, items)?,
             species: json_string(object.get("species")?)?,
             name: json_string(object.get("name")?)?,
@@ -285,17 +658,20 @@ impl Jsonizable for Monster {
 }
 
 impl Monster {
-    pub fn gen(
-        rng: &mut ThreadRng,
-        terrain: Terrain,
+    /// A monster of one of `species` in one of `tiles`. `None` if either is empty, like the
+    /// ocean region on a torus, which has no tiles.
+    pub fn gen<R: Rng + ?Sized>(
+        rng: &mut R,
+        species: &[Species],
         tiles: &[usize],
         items: &Items,
         markov_data_monster: &MarkovData,
-    ) -> Self {
-        let species = *terrain.monster_types().choose(rng).unwrap();
-        Self {
+    ) -> Option<Self> {
+        let species = *species.choose(rng)?;
+        let location = *tiles.choose(rng)?;
+        Some(Self {
             alive: true,
-            location: *tiles.choose(rng).unwrap(),
+            location,
             inventory: Inventory::default(items),
             species: String::from(species.as_ref()),
             name: markov_data_monster.sample(rng),
@@ -373,6 +749,71 @@ impl Monster {
                     ),
                 }
             },
-        }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Biome, Biomes, Border, Climate, Region};
+    use crate::{
+        sim::{Grid, Inventory, TileShape, Topology},
+        Items,
+    };
+
+    #[test]
+    fn borders_count_shared_edges() {
+        // a column each of ocean, plain and forest:
+        // 0 1 2
+        // 3 4 5
+        let grid = Grid::new(3, 2, Topology::Flat, TileShape::Square);
+        let items = Items::from_item_types(Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let columns = [
+            (vec![0, 3], "Ocean"),
+            (vec![1, 4], "Plain"),
+            (vec![2, 5], "Forest"),
+        ];
+        let mut regions: Vec<Region> = columns
+            .into_iter()
+            .enumerate()
+            .map(|(id, (tiles, terrain))| Region {
+                id,
+                tiles,
+                resources: Inventory::default(&items),
+                terrain: String::from(terrain),
+                climate: Climate::default(),
+                borders: BTreeMap::new(),
+                monster: None,
+            })
+            .collect();
+        Region::find_borders(&mut regions, grid, &Biomes::default());
+        let border = |length, coastal| Some(Border { length, coastal });
+        assert_eq!(regions[0].border(1), border(2, true));
+        assert_eq!(regions[1].border(0), border(2, true));
+        assert_eq!(regions[1].border(2), border(2, false));
+        assert_eq!(regions[2].border(1), border(2, false));
+        assert_eq!(regions[0].border(2), None);
+    }
+
+    #[test]
+    fn terrain_follows_climate() {
+        let biomes = Biomes::default();
+        assert_eq!(biomes.closest(Climate::new(0.5, 0.8, 0.1)), "Desert");
+        assert_eq!(biomes.closest(Climate::new(0.95, 0.3, 0.5)), "Mountain");
+        assert_eq!(biomes.closest(Climate::new(0.45, 0.5, 0.35)), "Plain");
+        // hot and wet like a jungle, but under water
+        assert_eq!(biomes.closest(Climate::new(0.3, 0.85, 0.9)), "Ocean");
+        // without a biome of the right kind, any will do
+        let plains = Biomes(BTreeMap::from([(
+            String::from("Plain"),
+            Biome::fallback("Plain").unwrap(),
+        )]));
+        assert_eq!(plains.closest(Climate::new(0.1, 0.5, 0.5)), "Plain");
+        assert_eq!(
+            Biomes(BTreeMap::new()).closest(Climate::default()),
+            Biomes::OCEAN
+        );
     }
 }
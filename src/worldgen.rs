@@ -1,26 +1,49 @@
-use crate::sim::{distance, get_adj, Region, Terrain};
+use crate::sim::{
+    routes_from, Biomes, Climate, Coord, Grid, Region, TradeRoute, Want, Water, Waters,
+};
 #[allow(clippy::wildcard_imports)]
 use crate::*;
 
 impl WorldGen {
-    pub fn sample(&self, rng: &mut ThreadRng, markov: &MarkovCollection) -> World {
-        let magic = MagicSystem::gen(rng, markov);
+    /// Generate a world from `seed`, which it keeps to be simulated with
+    #[must_use]
+    pub fn sample(&self, seed: u64, markov: &MarkovCollection) -> World {
+        let rng = &mut SimRng::seed_from_u64(seed);
+        let mut magic = MagicSystem::gen(rng, markov);
         let Items {
             all: _,
+            indices: _,
+            names: _,
+            pricing: _,
+            coin_metal: _,
             mut plants,
             mut metals,
             mut gems,
             animals,
         } = self.items.clone();
-        match &magic.material_type {
-            magic::MaterialType::Plant => &mut plants,
-            magic::MaterialType::Gem => &mut gems,
-            magic::MaterialType::Metal => &mut metals,
-        }
-        .push(magic.material.clone());
+        let (item_types, material): (_, fn(u8) -> Item) = match &magic.material_type {
+            magic::MaterialType::Plant => (&mut plants, Item::Plant),
+            magic::MaterialType::Gem => (&mut gems, Item::Gem),
+            magic::MaterialType::Metal => (&mut metals, Item::Metal),
+        };
+        item_types.push(magic.material.clone());
+        let material = material((item_types.len() - 1) as u8);
         let items = Items::from_item_types(plants, metals, gems, animals);
-        let (region_map, region_list) =
-            build_region_map(rng, &markov.monster, &self.config, &items);
+        magic.index = items.index(material);
+        let climates = generate_climates(rng, &self.config);
+        let (region_map, region_list) = build_region_map(
+            rng,
+            &markov.monster,
+            &self.config,
+            &items,
+            &self.biomes,
+            &climates,
+        );
+        let passable: Vec<bool> = region_map
+            .iter()
+            .map(|&region| self.biomes[region_list[region].terrain()].passable)
+            .collect();
+        let waters = Waters::gen(rng, self.config.grid(), &climates, &passable);
         let (city_list, trade_connections) = generate_cities(
             &region_map,
             &region_list,
@@ -28,146 +51,434 @@ impl WorldGen {
             &markov.name,
             &self.config,
             &items,
+            &self.biomes,
+            &waters,
         );
-        let trade_connections_list: Vec<(usize, usize)> =
-            trade_connections.iter().map(|(&k, _v)| k).collect();
+        let mut trade_connections_list: Vec<(usize, usize)> =
+            trade_connections.keys().copied().collect();
+        // keep the route order independent of the hashmap so seeded runs are reproducible
+        trade_connections_list.sort_unstable();
         World {
             config: self.config,
             current_year: 0,
+            seed,
+            rng: sim_rng(seed),
             region_map,
             region_list,
             city_list,
             trade_connections,
             trade_connections_list,
+            trade_history: Vec::new(),
+            trade_ledger: Vec::new(),
             items,
+            biomes: self.biomes.clone(),
+            waters,
             magic,
+            observers: Vec::new(),
+        }
+    }
+}
+
+/// Smoothed noise over the map, stretched to run from 0 to 1
+fn noise_field<R: Rng + ?Sized>(rng: &mut R, grid: Grid, passes: usize) -> Vec<f32> {
+    let mut field: Vec<f32> = grid.indices().map(|_| rng.gen()).collect();
+    for _ in 0..passes {
+        field = grid
+            .indices()
+            .map(|index| {
+                let neighbors = grid.neighbors(index, 1);
+                let sum: f32 = neighbors.iter().map(|&neighbor| field[neighbor]).sum();
+                (field[index] + sum) / (neighbors.len() + 1) as f32
+            })
+            .collect();
+    }
+    stretch(field.iter_mut(), 0.0, 1.0);
+    field
+}
+
+/// How many tiles `index` is from the nearest edge of the map that doesn't wrap
+fn edge_distance(grid: Grid, index: usize) -> f32 {
+    let topology = grid.topology();
+    let Coord { x, y } = grid.coord(index);
+    let distance = |position: usize, size: usize, wraps: bool| {
+        if wraps {
+            f32::MAX
+        } else {
+            position.min(size - 1 - position) as f32
+        }
+    };
+    distance(x, grid.width(), topology.wraps_x()).min(distance(
+        y,
+        grid.height(),
+        topology.wraps_y(),
+    ))
+}
+
+/// Where the continents are centred, spread out from each other and away from the edges
+fn continent_centers<R: Rng + ?Sized>(rng: &mut R, grid: Grid, continents: usize) -> Vec<usize> {
+    let mut centers: Vec<usize> = Vec::with_capacity(continents);
+    for _ in 0..continents {
+        // best of a handful of random candidates
+        let score = |&candidate: &usize| {
+            centers
+                .iter()
+                .map(|&center| grid.distance(candidate, center))
+                .fold(edge_distance(grid, candidate) * 2.0, f32::min)
+        };
+        let best = (0..10)
+            .map(|_| rng.gen_range(0..grid.len()))
+            .max_by(|a, b| score(a).total_cmp(&score(b)));
+        centers.extend(best);
+    }
+    centers
+}
+
+/// The elevation of every tile, shaped into the configured continents and islands. Exactly
+/// the configured share of the map ends up above [`Climate::SEA_LEVEL`].
+fn generate_elevation<R: Rng + ?Sized>(rng: &mut R, config: &Config) -> Vec<f32> {
+    let grid = config.grid();
+    let roughness = config.coastline_roughness;
+    let noise = noise_field(rng, grid, config.gen_radius);
+    let centers = continent_centers(rng, grid, config.continents);
+    // how far each continent reaches if they share the land out evenly
+    let reach = (config.land_ratio * grid.len() as f32
+        / (config.continents.max(1) as f32 * std::f32::consts::PI))
+        .sqrt()
+        .max(1.0);
+    let mut elevation = Vec::with_capacity(grid.len());
+    // continents are kept apart by a strait halfway between them
+    let mut strait = Vec::with_capacity(grid.len());
+    let mut open_sea = Vec::with_capacity(grid.len());
+    for index in grid.indices() {
+        let mut distances: Vec<f32> = centers
+            .iter()
+            .map(|&center| grid.distance(index, center))
+            .collect();
+        distances.sort_by(f32::total_cmp);
+        let height = match distances[..] {
+            [] => noise[index],
+            [nearest, ..] => (1.0 - nearest / (2.0 * reach))
+                .max(0.0)
+                .mul_add(1.0 - roughness, noise[index] * roughness),
+        };
+        // land sinks into the sea over the last few tiles before an edge that doesn't wrap
+        elevation.push(height * (edge_distance(grid, index) / 4.0).min(1.0));
+        strait.push(matches!(distances[..], [nearest, second, ..] if second - nearest <= 2.0));
+        open_sea.push(
+            distances
+                .first()
+                .is_none_or(|&nearest| nearest > 1.5 * reach),
+        );
+    }
+    // islands are scattered over the open sea, well away from the continents, and can take up
+    // to half of the land
+    let mut budget = (config.land_ratio * grid.len() as f32).round() as usize;
+    let mut island = vec![false; grid.len()];
+    let islands = (config.archipelago_density * grid.len() as f32 / 25.0).round() as usize;
+    let shallows: Vec<usize> = grid
+        .indices()
+        .filter(|&index| open_sea[index] && !strait[index] && !grid.is_edge(index))
+        .collect();
+    let mut island_tiles = 0;
+    for &center in shallows.choose_multiple(rng, islands) {
+        let neighbors = grid.neighbors(center, 1).into_iter();
+        for tile in std::iter::once(center).chain(neighbors.filter(|_| rng.gen_bool(0.5))) {
+            if island_tiles < budget / 2 && !island[tile] && !strait[tile] && !grid.is_edge(tile) {
+                island[tile] = true;
+                island_tiles += 1;
+            }
         }
     }
+    budget -= island_tiles;
+    // the highest tiles make up whatever land the islands leave over
+    let mut ranked: Vec<usize> = grid
+        .indices()
+        .filter(|&index| !island[index] && !strait[index] && !grid.is_edge(index))
+        .collect();
+    ranked.sort_by(|&a, &b| elevation[b].total_cmp(&elevation[a]));
+    let mut land = vec![false; grid.len()];
+    for &index in ranked.iter().take(budget) {
+        land[index] = true;
+    }
+    for (keep, low, high) in [
+        (true, Climate::SEA_LEVEL, 1.0),
+        (false, 0.0, Climate::SEA_LEVEL * 0.99),
+    ] {
+        let heights = elevation.iter_mut().zip(&land);
+        stretch(
+            heights.filter_map(|(height, &land)| (land == keep).then_some(height)),
+            low,
+            high,
+        );
+    }
+    for index in grid.indices().filter(|&index| island[index]) {
+        elevation[index] =
+            noise[index].mul_add((1.0 - Climate::SEA_LEVEL) * 0.5, Climate::SEA_LEVEL);
+    }
+    elevation
 }
 
-fn build_region_map(
-    rng: &mut ThreadRng,
+/// The elevation, temperature and rainfall of every tile
+fn generate_climates<R: Rng + ?Sized>(rng: &mut R, config: &Config) -> Vec<Climate> {
+    let grid = config.grid();
+    let topology = grid.topology();
+    let passes = config.gen_radius * 2;
+    let elevation = generate_elevation(rng, config);
+    let heat = noise_field(rng, grid, passes);
+    let rainfall = noise_field(rng, grid, passes);
+    grid.indices()
+        .map(|index| {
+            let Coord { y, .. } = grid.coord(index);
+            let elevation = elevation[index];
+            // warmest across the middle of the map, unless north and south join up
+            let temperature = if topology.wraps_y() {
+                heat[index]
+            } else {
+                let latitude = (y as f32 / (grid.height() - 1).max(1) as f32 - 0.5).abs() * 2.0;
+                0.6f32.mul_add(1.0 - latitude, 0.4 * heat[index])
+            };
+            // and colder up in the mountains
+            let temperature = 0.5f32
+                .mul_add(-(elevation - Climate::SEA_LEVEL).max(0.0), temperature)
+                .clamp(0.0, 1.0);
+            Climate::new(elevation, temperature, rainfall[index])
+        })
+        .collect()
+}
+
+/// Rescale `values` to run from `low` to `high`
+fn stretch<'a>(values: impl Iterator<Item = &'a mut f32>, low: f32, high: f32) {
+    let mut values: Vec<&mut f32> = values.collect();
+    let (min, max) = values.iter().fold((f32::MAX, f32::MIN), |(min, max), v| {
+        (min.min(**v), max.max(**v))
+    });
+    let range = (max - min).max(f32::EPSILON);
+    for value in &mut values {
+        **value = ((**value - min) / range).mul_add(high - low, low);
+    }
+}
+
+/// The average climate of each region. Averaging over a whole region evens things out, so
+/// they're stretched back out to cover the full range, keeping water regions under water and
+/// land above it. Regions without tiles have no climate to speak of and are left out.
+fn region_climates(
+    region_tiles: &[Vec<usize>],
+    climates: &[Climate],
+    water: &[bool],
+) -> Vec<Climate> {
+    let mut region_climates: Vec<Climate> = region_tiles
+        .iter()
+        .map(|tiles| Climate::mean(tiles.iter().map(|&tile| climates[tile])))
+        .collect();
+    let mut occupied: Vec<(&mut Climate, bool)> = region_climates
+        .iter_mut()
+        .zip(region_tiles)
+        .zip(water)
+        .filter(|((_, tiles), _)| !tiles.is_empty())
+        .map(|((climate, _), &water)| (climate, water))
+        .collect();
+    stretch(
+        occupied.iter_mut().map(|(c, _)| &mut c.temperature),
+        0.0,
+        1.0,
+    );
+    stretch(occupied.iter_mut().map(|(c, _)| &mut c.rainfall), 0.0, 1.0);
+    let (land, sea): (Vec<_>, Vec<_>) = occupied.into_iter().partition(|&(_, water)| !water);
+    stretch(
+        land.into_iter().map(|(c, _)| &mut c.elevation),
+        Climate::SEA_LEVEL,
+        1.0,
+    );
+    stretch(
+        sea.into_iter().map(|(c, _)| &mut c.elevation),
+        0.0,
+        Climate::SEA_LEVEL * 0.99,
+    );
+    region_climates
+}
+
+/// Split the map into regions, each all land or all water so coastlines follow the elevation.
+/// Seeds are scattered more than `gen_radius` apart and then grown all at once, a random
+/// frontier tile at a time, which takes time in proportion to the size of the map.
+fn build_region_map<R: Rng + ?Sized>(
+    rng: &mut R,
     markov_data_monster: &MarkovData,
     config: &Config,
     items: &Items,
+    biomes: &Biomes,
+    climates: &[Climate],
 ) -> (Vec<usize>, Vec<Region>) {
-    let mut regions = 0;
-    let mut region_map = vec![None; config.world_size.0 * config.world_size.1];
-    for y in 0..config.world_size.1 {
-        region_map[y * config.world_size.0] = Some(0);
-        region_map[config.world_size.0 + y * config.world_size.0 - 1] = Some(0);
-    }
-    for x in 0..config.world_size.0 {
-        region_map[x] = Some(0);
-        region_map[(config.world_size.1 - 1) * config.world_size.0 + x] = Some(0);
+    let grid = config.grid();
+    let is_water = |tile: usize| climates[tile].is_water();
+    let mut region_map: Vec<Option<usize>> = vec![None; grid.len()];
+    // the edge of the map, if the topology leaves one, is always ocean: region 0
+    let mut frontier: Vec<usize> = grid.indices().filter(|&tile| grid.is_edge(tile)).collect();
+    let edge_ocean = !frontier.is_empty();
+    let mut water = if edge_ocean { vec![true] } else { Vec::new() };
+    // no two seeds of the same kind within `gen_radius` tiles of each other
+    let mut crowded = vec![false; grid.len()];
+    let crowd = |crowded: &mut Vec<bool>, seed: usize| {
+        for neighbor in grid.neighbors(seed, config.gen_radius + 1) {
+            if is_water(neighbor) == is_water(seed) {
+                crowded[neighbor] = true;
+            }
+        }
+    };
+    for &tile in &frontier {
+        region_map[tile] = Some(0);
+        crowd(&mut crowded, tile);
     }
-    let mut indices: Vec<usize> = (0..(config.world_size.0 * config.world_size.1)).collect();
+    let mut unclaimed: Vec<usize> = grid.indices().collect();
+    unclaimed.shuffle(rng);
     loop {
-        indices.shuffle(rng);
-        for index in indices.clone() {
-            if region_map.get(index).map_or(false, Option::is_some) {
-                indices.remove(
-                    indices
-                        .iter()
-                        .position(|x| *x == index)
-                        .expect("Index somehow gone already??"),
-                );
-                continue;
+        for &tile in &unclaimed {
+            if region_map[tile].is_none() && !crowded[tile] {
+                region_map[tile] = Some(water.len());
+                water.push(is_water(tile));
+                frontier.push(tile);
+                crowd(&mut crowded, tile);
             }
-            for n in 0..config.gen_radius {
-                let adj: Vec<usize> = get_adj(index, n, config)
-                    .iter()
-                    .filter_map(|&m| region_map[m])
-                    .collect();
-                if adj.is_empty() {
-                    continue;
+        }
+        while !frontier.is_empty() {
+            let tile = frontier.swap_remove(rng.gen_range(0..frontier.len()));
+            let region = region_map[tile].expect("Frontier tiles always have a region");
+            for neighbor in grid.neighbors(tile, 1) {
+                if region_map[neighbor].is_none() && is_water(neighbor) == water[region] {
+                    region_map[neighbor] = Some(region);
+                    frontier.push(neighbor);
                 }
-                region_map[index] = adj.choose(rng).copied();
-                break;
-            }
-            if region_map.get(index).map_or(false, Option::is_none) {
-                regions += 1;
-                region_map[index] = Some(regions);
-                indices.remove(
-                    indices
-                        .iter()
-                        .position(|x| *x == index)
-                        .expect("Index somehow gone already??"),
-                );
             }
         }
-        if !indices.iter().any(|&item| region_map[item].is_none()) {
+        // pockets that no seed could reach get seeds of their own
+        unclaimed.retain(|&tile| region_map[tile].is_none());
+        if unclaimed.is_empty() {
             break;
         }
+        for &tile in &unclaimed {
+            crowded[tile] = false;
+        }
     }
-    let region_map_fixed: Vec<usize> = region_map.iter().map(|&m| m.unwrap_or(0)).collect();
-    let mut region_list: Vec<Region> = (0..regions)
-        .map(|id| {
-            Region::gen(
-                id + 1,
-                &region_map_fixed,
-                rng,
-                regions,
-                markov_data_monster,
-                config,
-                items,
-            )
+    let region_map: Vec<usize> = region_map.iter().map(|&m| m.unwrap_or(0)).collect();
+    let mut region_tiles = vec![Vec::new(); water.len()];
+    for (tile, &region) in region_map.iter().enumerate() {
+        region_tiles[region].push(tile);
+    }
+    let region_climates = region_climates(&region_tiles, climates, &water);
+    let mut region_list: Vec<Region> = region_tiles
+        .into_iter()
+        .zip(region_climates)
+        .enumerate()
+        .map(|(id, (tiles, climate))| {
+            Region::gen(id, tiles, rng, markov_data_monster, items, biomes, climate)
         })
         .collect();
-    region_list.insert(0, {
-        let mut base_region = Region::gen(
-            0,
-            &region_map_fixed,
-            rng,
-            regions,
-            markov_data_monster,
-            config,
-            items,
-        );
-        base_region.set_terrain(Terrain::Ocean);
-        base_region
-    });
-    (region_map_fixed, region_list)
+    if edge_ocean {
+        region_list[0].set_terrain(Biomes::OCEAN);
+    }
+    Region::find_borders(&mut region_list, grid, biomes);
+    (region_map, region_list)
 }
 
-fn generate_cities(
+/// Cities go on the most promising tiles first: those with rich regions around them, on a
+/// coast or by fresh water, in welcoming terrain, and away from the cities already placed.
+/// Each keeps the configured distance from the others. Unless the config sets a count, the
+/// city densities decide how many there are.
+fn place_cities<R: Rng + ?Sized>(
     region_map: &[usize],
     region_list: &[Region],
-    rng: &mut ThreadRng,
-    markov_data: &MarkovData,
+    rng: &mut R,
     config: &Config,
-    items: &Items,
-) -> (HashMap<usize, City>, HashMap<(usize, usize), i32>) {
+    biomes: &Biomes,
+    waters: &Waters,
+) -> Vec<usize> {
+    let grid = config.grid();
+    let biome = |tile: usize| &biomes[region_list[region_map[tile]].terrain()];
+    let wealth: Vec<f32> = region_list
+        .iter()
+        .map(|region| region.resources().iter().map(|(_, amount)| amount).sum())
+        .collect();
     let mut possible_cities = Vec::new();
-    for x in 0..region_map.len() {
-        if region_list[region_map[x]].terrain() == Terrain::Ocean {
+    let mut expected_cities = 0.0;
+    for x in grid.indices() {
+        let suitability = biome(x).city_suitability;
+        if !biome(x).passable || waters.get(x) == Some(Water::Lake) || suitability <= 0.0 {
             continue;
         }
-        if get_adj(x, 1, config)
-            .iter()
-            .any(|&m| region_list[region_map[m]].terrain() == Terrain::Ocean)
-        {
-            if rng.gen::<f32>() > config.coastal_city_density {
-                continue;
-            }
-        } else if rng.gen::<f32>() > config.inland_city_density {
-            continue;
+        let nearby = grid.neighbors(x, 1);
+        let coastal = nearby.iter().any(|&m| !biome(m).passable);
+        let fresh_water = waters.is_near(grid, x);
+        // rivers draw cities just like the coast does
+        expected_cities += suitability
+            * if coastal || fresh_water {
+                config.coastal_city_density
+            } else {
+                config.inland_city_density
+            };
+        // everything the regions within a step have to offer, so the sea's fish count too
+        let mut regions: Vec<usize> = std::iter::once(x)
+            .chain(nearby)
+            .map(|m| region_map[m])
+            .collect();
+        regions.sort_unstable();
+        regions.dedup();
+        let resources: f32 = regions.iter().map(|&region| wealth[region]).sum();
+        let mut access = 1.0;
+        if coastal {
+            access += 0.5;
+        }
+        if fresh_water {
+            access += 0.5;
         }
-        possible_cities.push(x);
+        let score = resources * access * suitability * rng.gen_range(0.5..1.0);
+        possible_cities.push((x, score));
     }
+    let target = config
+        .city_count
+        .unwrap_or_else(|| expected_cities.round() as usize);
+    let spacing = config.city_spacing;
+    // a city takes trade from the land around it, so spots count for less the closer they are
+    // to a city, up to twice the spacing away
+    let reach = (2.0 * spacing).max(1.0);
+    let mut nearest_city = vec![f32::INFINITY; possible_cities.len()];
     let mut actual_cities = Vec::new();
-    possible_cities.shuffle(rng);
-    for x in possible_cities {
-        // Discard a city if there's already a city adjacent to it
-        if get_adj(x, 1, config)
+    while actual_cities.len() < target {
+        // spots closer than the spacing to a city are out
+        let best = possible_cities
             .iter()
-            .any(|&x| actual_cities.iter().any(|&c| x == c))
-        {
-            continue;
+            .zip(&nearest_city)
+            .enumerate()
+            .filter(|(_, (_, &distance))| distance >= spacing)
+            .map(|(i, (&(_, score), &distance))| (i, score * (distance / reach).min(1.0)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        let Some((best, _)) = best else { break };
+        let (x, _) = possible_cities.swap_remove(best);
+        nearest_city.swap_remove(best);
+        for (&(m, _), distance) in possible_cities.iter().zip(&mut nearest_city) {
+            *distance = distance.min(grid.distance(x, m));
         }
         actual_cities.push(x);
     }
-    return (
+    actual_cities
+}
+
+#[allow(clippy::too_many_arguments)]
+fn generate_cities<R: Rng + ?Sized>(
+    region_map: &[usize],
+    region_list: &[Region],
+    rng: &mut R,
+    markov_data: &MarkovData,
+    config: &Config,
+    items: &Items,
+    biomes: &Biomes,
+    waters: &Waters,
+) -> (BTreeMap<usize, City>, HashMap<(usize, usize), TradeRoute>) {
+    let grid = config.grid();
+    let actual_cities = place_cities(region_map, region_list, rng, config, biomes, waters);
+    let mut is_city = vec![false; grid.len()];
+    for &x in &actual_cities {
+        is_city[x] = true;
+    }
+    (
         actual_cities
             .iter()
             .map(|&pos| {
@@ -176,31 +487,218 @@ fn generate_cities(
                     City::new(
                         pos,
                         markov_data.sample(rng),
-                        Inventory::from(
-                            region_list[region_map[pos]]
-                                .resources()
-                                .iter()
-                                .enumerate()
-                                .map(|(_, &val)| rng.gen::<f32>().mul_add(0.1, val))
-                                .collect::<Vec<_>>(),
-                        ),
+                        {
+                            let mut gathering = Inventory::from(
+                                region_list[region_map[pos]]
+                                    .resources()
+                                    .iter()
+                                    .map(|(_, val)| rng.gen::<f32>().mul_add(0.1, val))
+                                    .collect::<Vec<_>>(),
+                            );
+                            // a river or lake is as good for fishing as a typical region
+                            if let Some(fish) = items.index(Item::Fish) {
+                                if waters.is_near(grid, pos) {
+                                    gathering.add(fish, rng.gen::<f32>() + 1.0);
+                                }
+                            }
+                            gathering
+                        },
+                        Want::iter()
+                            .map(|want| (want, rng.gen_range(0.5..1.5)))
+                            .collect(),
                         items,
                     ),
                 )
             })
             .collect(),
         {
-            // Trade Connections
-            let mut trade_connections: HashMap<(usize, usize), i32> = HashMap::new();
+            // Trade Connections, the cheaper of the land and sea routes between each pair
+            let biome = |tile: usize| &biomes[region_list[region_map[tile]].terrain()];
+            let mut trade_connections: HashMap<(usize, usize), TradeRoute> = HashMap::new();
             for &start in &actual_cities {
-                trade_connections.extend(
-                    actual_cities
-                        .iter()
-                        .filter(|&&end| end > start && distance(end, start, config) < 5.0)
-                        .map(|&end| ((start, end), 0)),
+                let routes = routes_from(
+                    grid,
+                    start,
+                    biome,
+                    waters,
+                    |tile| is_city[tile],
+                    config.trade_reach,
                 );
+                for (end, route) in routes {
+                    let key = (start.min(end), start.max(end));
+                    if trade_connections
+                        .get(&key)
+                        .is_none_or(|known| route.cost < known.cost)
+                    {
+                        trade_connections.insert(key, route);
+                    }
+                }
             }
             trade_connections
         },
-    );
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use json::object;
+    use rand::{rngs::mock::StepRng, SeedableRng};
+
+    use super::{build_region_map, generate_elevation, place_cities, region_climates};
+    use crate::{
+        jsonize::Jsonizable,
+        mkv::MarkovCollection,
+        sim::{Biomes, Climate, Coord, Region, Topology, Waters},
+        Config, Items, SimRng,
+    };
+
+    #[test]
+    fn regions_are_connected() {
+        let markov = MarkovCollection::builtin();
+        let items = Items::from_item_types(Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for topology in [Topology::Flat, Topology::Torus] {
+            let config = Config {
+                world_size: (12, 8),
+                gen_radius: 1,
+                topology,
+                ..Config::default()
+            };
+            let grid = config.grid();
+            // land to the west with a pond in it, sea to the east
+            let pond = grid.index(Coord::new(3, 4)).unwrap();
+            let climates: Vec<Climate> = grid
+                .indices()
+                .map(|tile| {
+                    let land = grid.coord(tile).x < 7 && tile != pond;
+                    Climate::new(if land { 0.6 } else { 0.2 }, 0.5, 0.5)
+                })
+                .collect();
+            let (region_map, regions) = build_region_map(
+                &mut SimRng::seed_from_u64(3),
+                &markov.monster,
+                &config,
+                &items,
+                &Biomes::default(),
+                &climates,
+            );
+            assert!(regions.len() > 3);
+            // only a map with edges has the ocean around them, whatever the land there is like
+            let edges: Vec<usize> = grid.indices().filter(|&tile| grid.is_edge(tile)).collect();
+            assert_eq!(edges.is_empty(), topology == Topology::Torus);
+            assert!(edges.iter().all(|&tile| region_map[tile] == 0));
+            if !edges.is_empty() {
+                assert_eq!(regions[0].terrain(), Biomes::OCEAN);
+            }
+            for (id, region) in regions.iter().enumerate() {
+                assert_eq!(region.id(), id);
+                let tiles = region.tiles();
+                assert!(!tiles.is_empty());
+                assert!(tiles.iter().all(|&tile| region_map[tile] == id));
+                if edges.is_empty() || id != 0 {
+                    let water = climates[tiles[0]].is_water();
+                    assert!(tiles.iter().all(|&tile| climates[tile].is_water() == water));
+                }
+                let mut reached = vec![tiles[0]];
+                let mut frontier = vec![tiles[0]];
+                while let Some(tile) = frontier.pop() {
+                    for neighbor in grid.neighbors(tile, 1) {
+                        if tiles.contains(&neighbor) && !reached.contains(&neighbor) {
+                            reached.push(neighbor);
+                            frontier.push(neighbor);
+                        }
+                    }
+                }
+                assert_eq!(reached.len(), tiles.len());
+            }
+            assert_eq!(*regions[region_map[pond]].tiles(), [pond]);
+        }
+    }
+
+    #[test]
+    fn land_matches_the_configured_ratio() {
+        for (land_ratio, archipelago_density) in [(0.3, 0.0), (0.5, 2.0)] {
+            let config = Config {
+                world_size: (20, 12),
+                land_ratio,
+                continents: 2,
+                archipelago_density,
+                ..Config::default()
+            };
+            let grid = config.grid();
+            let elevation = generate_elevation(&mut SimRng::seed_from_u64(3), &config);
+            assert_eq!(elevation.len(), grid.len());
+            assert!(elevation.iter().all(|height| (0.0..=1.0).contains(height)));
+            let land = elevation
+                .iter()
+                .filter(|&&height| height >= Climate::SEA_LEVEL)
+                .count();
+            assert_eq!(land, (land_ratio * grid.len() as f32).round() as usize);
+            // a flat map's edges are always sea
+            assert!(grid
+                .indices()
+                .filter(|&index| grid.is_edge(index))
+                .all(|index| elevation[index] < Climate::SEA_LEVEL));
+        }
+    }
+
+    #[test]
+    fn cities_spread_over_the_richest_land() {
+        // a row of plains, one region to a tile, with this much fish in each
+        let fish = [0, 0, 1, 8, 3, 3, 0, 0, 0, 0, 0, 0, 1, 3, 1, 0];
+        let spaced = |city_spacing| Config {
+            world_size: (fish.len(), 1),
+            city_count: Some(3),
+            city_spacing,
+            ..Config::default()
+        };
+        let config = spaced(2.0);
+        let grid = config.grid();
+        let items = Items::from_item_types(Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let regions: Vec<Region> = fish
+            .iter()
+            .enumerate()
+            .map(|(tile, &fish)| {
+                let region = object! {
+                    tiles: [grid.coord(tile)],
+                    resources: {Fish: fish},
+                    terrain: "Plain",
+                    monster: null,
+                };
+                Region::dejsonize(&region, &config, &items).unwrap()
+            })
+            .collect();
+        let region_map: Vec<usize> = grid.indices().collect();
+        let biomes = Biomes::default();
+        let waters = Waters::new(grid.len());
+        let place = |config: &Config| {
+            // every score gets the same roll
+            let rng = &mut StepRng::new(0, 0);
+            place_cities(&region_map, &regions, rng, config, &biomes, &waters)
+        };
+        // the richest spot goes first; tile 2 has more around it than tile 13, but it's right
+        // by the first city, so the far side of the map is settled before it
+        assert_eq!(place(&config), [4, 13, 2]);
+        // no room for more than one
+        assert_eq!(place(&spaced(20.0)), [4]);
+    }
+
+    #[test]
+    fn empty_regions_stay_out_of_the_stretch() {
+        let climates = [
+            Climate::new(0.1, 0.2, 0.3),
+            Climate::new(0.6, 0.4, 0.5),
+            Climate::new(0.8, 0.9, 0.7),
+        ];
+        let with_empty = region_climates(
+            &[vec![], vec![0], vec![1], vec![2]],
+            &climates,
+            &[true, true, false, false],
+        );
+        let without = region_climates(
+            &[vec![0], vec![1], vec![2]],
+            &climates,
+            &[true, false, false],
+        );
+        assert_eq!(&with_empty[1..], &without[..]);
+    }
 }